@@ -1,17 +1,21 @@
 ; Hello world
-ldc 72 ; H
 ldc 65536 ; TERMINAL OUTPUT REGISTER
+stl 0
+ldc 72 ; H
+ldl 0
 stnl 0
 ldc 69 ; E
-rev
+ldl 0
 stnl 0
 ldc 76 ; L
-rev
+ldl 0
 stnl 0
+ldc 76 ; L
+ldl 0
 stnl 0
 ldc 79 ; O
-rev
+ldl 0
 stnl 0
 ldc 10 ; \n
-rev
+ldl 0
 stnl 0
\ No newline at end of file
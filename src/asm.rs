@@ -1,10 +1,15 @@
-use std::{any::Any, collections::HashMap};
+use std::collections::HashMap;
+use std::fmt;
+use std::fs;
+use std::io;
+use std::path::Path;
 
-use crate::proc::{DirectOp, Proc};
+use crate::proc::{DirectOp, Encoding, Proc};
 
-fn prefix_constant(op: u8, v: i32) -> Vec<u8>{
-    if v < 16 && v >= 0{
-        return vec![(op << 4) + (v as u8)];
+/// Encode a direct operation with any prefixes its operand needs
+pub fn prefix_constant(op: u8, v: i32) -> Vec<u8>{
+    if (0..16).contains(&v){
+        vec![(op << 4) + (v as u8)]
     }
     else if v >= 16{
         let mut p = Vec::new();
@@ -12,7 +17,7 @@ fn prefix_constant(op: u8, v: i32) -> Vec<u8>{
             p.push(fix);
         }
         p.push((op << 4) + ((v & 0xF) as u8));
-        return p
+        p
     }
     else{
         let mut p = Vec::new();
@@ -20,7 +25,69 @@ fn prefix_constant(op: u8, v: i32) -> Vec<u8>{
             p.push(fix);
         }
         p.push((op << 4) + ((v & 0xF) as u8));
-        return p
+        p
+    }
+}
+
+/// Turn an image back into source the assembler accepts, one instruction
+/// per line with prefixes folded into the operand they build
+/// Operations through fpentry are named when the ldc before it selects one
+pub fn disassemble(proc: &Proc, image: &[u8]) -> Vec<String>{
+    let mut lines = Vec::new();
+    let mut operand: i32 = 0;
+    let mut last_ldc = None;
+    for byte in image{
+        let op = DirectOp::from_instruction(*byte);
+        let v = operand.wrapping_add((byte & 0xF) as i32);
+        operand = 0;
+        let line = match op{
+            DirectOp::PFIX => {
+                operand = v << 4;
+                continue;
+            },
+            DirectOp::NFIX => {
+                operand = (!v) << 4;
+                continue;
+            },
+            DirectOp::OPR => match proc.indirect_info(v as usize){
+                Some(info) if info.mnemonic == "fpentry" => {
+                    match last_ldc.and_then(|code: i32| proc.fpentry_info(code as usize)){
+                        Some(fp) => {
+                            lines.pop();
+                            fp.mnemonic.to_string()
+                        },
+                        None => info.mnemonic.to_string()
+                    }
+                },
+                Some(info) => info.mnemonic.to_string(),
+                None => format!("opr {}", v)
+            },
+            _ => format!("{} {}", op.info().mnemonic, v)
+        };
+        last_ldc = if op == DirectOp::LDC { Some(v) } else { None };
+        lines.push(line);
+    }
+    lines
+}
+
+/// Why a line could not be assembled, with the line number counted from 1
+#[derive(Debug, Clone, PartialEq)]
+pub enum AsmErr{
+    /// Not an operation of the processor the assembler was set up for
+    UnknownOp(usize, String),
+    /// Label used before it was defined
+    UndefinedLabel(usize, String),
+    /// Direct operation at the end of a line
+    MissingOperand(usize, String)
+}
+
+impl fmt::Display for AsmErr{
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self{
+            AsmErr::UnknownOp(line, op) => write!(f, "line {}: unknown operation {}", line, op),
+            AsmErr::UndefinedLabel(line, label) => write!(f, "line {}: undefined label {}", line, label),
+            AsmErr::MissingOperand(line, op) => write!(f, "line {}: {} needs an operand", line, op)
+        }
     }
 }
 
@@ -58,7 +125,15 @@ pub struct Assemble{
     labels: HashMap<String, i32>,
     op: HashMap<String, u8>,
     iop: HashMap<String, usize>,
-    line_number: usize
+    fpop: HashMap<String, usize>,
+    line_number: usize,
+    source_line: usize
+}
+
+impl Default for Assemble{
+    fn default() -> Self{
+        Self::new()
+    }
 }
 
 impl Assemble{
@@ -67,30 +142,21 @@ impl Assemble{
             labels: HashMap::new(),
             op: HashMap::new(),
             iop: HashMap::new(),
-            line_number: 0
+            fpop: HashMap::new(),
+            line_number: 0,
+            source_line: 0
         }
     }
     
     pub fn setup(&mut self, proc: &Proc){
-        self.define_op("j", 0x0);
-        self.define_op("ldlp", 0x1);
-        self.define_op("pfix", 0x2);
-        self.define_op("ldnl", 0x3);
-        self.define_op("ldc", 0x4);
-        self.define_op("ldnlp", 0x5);
-        self.define_op("nfix", 0x6);
-        self.define_op("ldl", 0x7);
-        self.define_op("adc", 0x8);
-        self.define_op("call", 0x9);
-        self.define_op("cj", 0xA);
-        self.define_op("ajw", 0xB);
-        self.define_op("eqc", 0xC);
-        self.define_op("stl", 0xD);
-        self.define_op("stnl", 0xE);
-        self.define_op("opr", 0xF);
-        
-        for (op, value) in proc.get_indirect_ops(){
-            self.define_iop(op.clone(), value);
+        for info in proc.instruction_set(){
+            match info.encoding{
+                Encoding::Direct => self.define_op(info.mnemonic, info.code as u8),
+                Encoding::Indirect => self.define_iop(info.mnemonic.to_string(), info.code),
+                Encoding::FpEntry => {
+                    self.fpop.insert(info.mnemonic.to_string(), info.code);
+                }
+            }
         }
     }
     
@@ -102,64 +168,90 @@ impl Assemble{
         self.iop.insert(word, value);
     }
     
-    fn load_token(&mut self, t: &Token) -> Option<Vec<u8>>{
+    fn load_token(&mut self, t: &Token) -> Result<Option<Vec<u8>>, AsmErr>{
         match t{
             Token::Label(label) => {
-                self.labels.insert(label.clone(), self.line_number as i32);
-                None
+                self.labels.insert(label.trim_end_matches(':').to_string(), self.line_number as i32);
+                Ok(None)
             },
             Token::Op((op, v)) => {
                 let opcode = self.op[op];
-                Some(prefix_constant(opcode, *v))
+                Ok(Some(prefix_constant(opcode, *v)))
             },
             Token::IndirectOp(op) => {
-                let opcode = 0xF;
-                let v = self.iop[op];
-                Some(prefix_constant(opcode, v as i32))
+                // ldc code; fpentry for the operations fpentry selects
+                let fpentry = self.iop.get("fpentry");
+                match (self.fpop.get(op), fpentry, self.iop.get(op)){
+                    (Some(code), Some(fpentry), _) => {
+                        let mut ops = prefix_constant(0x4, *code as i32);
+                        ops.extend(prefix_constant(0xF, *fpentry as i32));
+                        Ok(Some(ops))
+                    },
+                    (_, _, Some(code)) => Ok(Some(prefix_constant(0xF, *code as i32))),
+                    _ => Err(AsmErr::UnknownOp(self.source_line, op.clone()))
+                }
             },
             Token::OpPromise( (op, label) ) => {
                 let opcode = self.op[op];
-                let c = self.labels[label];
-                Some(prefix_constant(opcode, c))
+                match self.labels.get(label){
+                    Some(c) => Ok(Some(prefix_constant(opcode, *c))),
+                    None => Err(AsmErr::UndefinedLabel(self.source_line, label.clone()))
+                }
             }
         }
     }
     
-    pub fn read_line(&mut self, line: &str) -> Option<Vec<u8>>{
-        match self.tokenize(line.to_string()){
-            Some(tokens) => {
-                let mut ops = Vec::new();
-                for t in tokens{
-                    if let Some(prefixed_op) = self.load_token(&t){
-                        for o in prefixed_op{
-                            self.line_number += 1;
-                            ops.push(o);
-                        }
-                    }
-                }
-                if ops.len() > 0{
-                    Some(ops)
-                }
-                else{
-                    None
-                }
-            },
-            None => None
+    pub fn read_line(&mut self, line: &str) -> Result<Option<Vec<u8>>, AsmErr>{
+        self.source_line += 1;
+        let mut ops = Vec::new();
+        for t in self.tokenize(line)?{
+            if let Some(prefixed_op) = self.load_token(&t)?{
+                self.line_number += prefixed_op.len();
+                ops.extend(prefixed_op);
+            }
+        }
+        if !ops.is_empty(){
+            Ok(Some(ops))
+        }
+        else{
+            Ok(None)
+        }
+    }
+    
+    /// Assemble a whole source listing into an image
+    pub fn assemble(&mut self, source: &str) -> Result<Vec<u8>, AsmErr>{
+        self.source_line = 0;
+        let mut image = Vec::new();
+        for line in source.lines(){
+            if let Some(values) = self.read_line(line)?{
+                image.extend(values);
+            }
         }
+        Ok(image)
     }
     
-    fn read(&self, reader: &mut Reader) -> Option<Token>{
+    /// Assemble a source file into an image, a line that can't be
+    /// assembled is reported as invalid data
+    pub fn assemble_file<P: AsRef<Path>>(&mut self, path: P) -> io::Result<Vec<u8>>{
+        let source = fs::read_to_string(path)?;
+        self.assemble(&source).map_err(|e| io::Error::new(io::ErrorKind::InvalidData, e.to_string()))
+    }
+    
+    fn read(&self, reader: &mut Reader) -> Result<Option<Token>, AsmErr>{
         let w = reader.get();
         if w.ends_with(":"){
             // Label
-            return Some(Token::Label(w));
+            return Ok(Some(Token::Label(w)));
         }
         if self.op.contains_key(&w){
             reader.next();
+            if reader.done(){
+                return Err(AsmErr::MissingOperand(self.source_line, w));
+            }
             let v = reader.get();
-            return match v.parse::<i32>(){
+            return Ok(match v.parse::<i32>(){
                 Ok(num) => Some(Token::Op( (w, num) )),
-                Err(e) => {
+                Err(_) => {
                     if self.labels.contains_key(&v){
                         let label_address = self.labels[&v];
                         Some(Token::Op( (w, label_address) ))
@@ -168,39 +260,31 @@ impl Assemble{
                         Some(Token::OpPromise((w, v)))
                     }
                 }
-            };
+            });
         }
-        match w.as_str(){
-            ";" =>  {reader.finish(); return None;},
-            _ => {
-                Some(Token::IndirectOp(w.clone()))
-            }
+        if w.starts_with(";"){
+            reader.finish();
+            return Ok(None);
         }
+        Ok(Some(Token::IndirectOp(w.clone())))
     }
     
-    fn tokenize(&self, line: String) -> Option<Vec<Token>>{
+    fn tokenize(&self, line: &str) -> Result<Vec<Token>, AsmErr>{
         // Split by spaces
-        let line_trimmed = line.trim().to_string();
-        let words: Vec<&str> = line_trimmed.split(" ").collect();
+        let mut reader = Reader{
+            words: line.split_whitespace().collect(),
+            pos: 0
+        };
+        
+        let mut tokens = Vec::new();
         
-        if words.len() > 0{
-            let mut reader = Reader{
-                words: words,
-                pos: 0
-            };
-            
-            let mut tokens = Vec::new();
-            
-            while !reader.done(){
-                match self.read(&mut reader){
-                    Some(t) => tokens.push(t),
-                    _ => ()
-                };
-                reader.next();
+        while !reader.done(){
+            if let Some(t) = self.read(&mut reader)?{
+                tokens.push(t);
             }
-            
-            return Some(tokens);
+            reader.next();
         }
-        None
+        
+        Ok(tokens)
     }
-}
\ No newline at end of file
+}
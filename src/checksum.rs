@@ -0,0 +1,43 @@
+/* CRCs as computed by the crcword and crcbyte instructions */
+
+use crc::{Crc, CRC_32_ISO_HDLC};
+
+/// The usual 32 bit CRC, for checking images against other tools
+const CRC_32: Crc<u32> = Crc::<u32>::new(&CRC_32_ISO_HDLC);
+
+/// Shift the top bits of data into the CRC register most significant bit first,
+/// the generator is xored in whenever a one shifts out of the top
+pub fn crc_shift(mut crc: u32, mut data: u32, generator: u32, bits: u32) -> u32{
+    for _ in 0..bits{
+        let top = crc & 0x8000_0000 != 0;
+        crc = (crc << 1) | (data >> 31);
+        data <<= 1;
+        if top{
+            crc ^= generator;
+        }
+    }
+    crc
+}
+
+/// crcword, all 32 bits of data
+pub fn crc_word(crc: u32, data: u32, generator: u32) -> u32{
+    crc_shift(crc, data, generator, 32)
+}
+
+/// crcbyte, the byte is held in the top 8 bits of data
+pub fn crc_byte(crc: u32, data: u32, generator: u32) -> u32{
+    crc_shift(crc, data, generator, 8)
+}
+
+/// CRC of bytes with an arbitrary generator, the way a program would compute it
+/// with crcbyte. Flushing 32 zero bits through the register gives the standard,
+/// unreflected, CRC with no initial value or final xor.
+pub fn crc_bytes(generator: u32, bytes: &[u8]) -> u32{
+    let crc = bytes.iter().fold(0, |crc, b| crc_byte(crc, (*b as u32) << 24, generator));
+    crc_word(crc, 0, generator)
+}
+
+/// Standard CRC-32 of bytes
+pub fn crc32(bytes: &[u8]) -> u32{
+    CRC_32.checksum(bytes)
+}
@@ -0,0 +1,7 @@
+pub mod proc;
+pub mod mem;
+pub mod checksum;
+
+pub mod asm;
+
+pub mod visual;
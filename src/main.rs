@@ -1,56 +1,70 @@
-use asm::Assemble;
-#[warn(unused_imports)]
+use t800_emulator::asm::Assemble;
+use t800_emulator::proc::{LoadConfig, Model, Proc, TimerMode};
 
-use proc::Proc;
+use std::env;
+use std::fs;
 
-mod proc;
-mod mem;
-
-mod asm;
-
-mod visual;
-
-use std::fs::File;
-use std::io::{self, BufRead};
-use std::path::Path;
-
-// The output is wrapped in a Result to allow matching on errors.
-// Returns an Iterator to the Reader of the lines of the file.
-fn read_lines<P>(filename: P) -> io::Result<io::Lines<io::BufReader<File>>>
-where P: AsRef<Path>, {
-    let file = File::open(filename)?;
-    Ok(io::BufReader::new(file).lines())
-}
+/// Address programs are loaded to
+const PROGRAM_BASE: i32 = 0x0000_1000;
+/// Initial workspace pointer
+const WORKSPACE: i32 = 0x0800_0000;
+/// Stop runaway programs
+const MAX_STEPS: usize = 100_000;
 
 fn main() {
+    // Assembly listings end in .s, anything else is loaded as a raw binary
+    // --paced keeps the transputer clocks in step with the wall clock
+    // --model=T414 (T425, T800, T805) picks the processor, T800 by default
+    let args: Vec<String> = env::args().skip(1).collect();
+    let paced = args.iter().any(|a| a == "--paced");
+    let model = match args.iter().find_map(|a| a.strip_prefix("--model=")).map(|m| m.parse::<Model>()){
+        Some(Ok(model)) => model,
+        Some(Err(e)) => {
+            println!("{}", e);
+            return;
+        }
+        None => Model::T800
+    };
+    let path = args.into_iter().find(|a| !a.starts_with("--")).unwrap_or("lib/hello.s".to_string());
     
-    let mut proc = Proc::new(0x1000_0000);
-    
-    let mut asm = Assemble::new();
-    asm.setup(&proc);
+    let mut proc = match Proc::with_model(WORKSPACE, model.descriptor()){
+        Ok(proc) => proc,
+        Err(e) => {
+            println!("Invalid model: {:?}", e);
+            return;
+        }
+    };
+    if paced{
+        proc.set_timer_mode(TimerMode::Paced);
+    }
+    let config = LoadConfig::new(PROGRAM_BASE, WORKSPACE);
     
-    let mut machine: Vec<u8> = Vec::new();
+    let image = if path.ends_with(".s"){
+        let mut asm = Assemble::new();
+        asm.setup(&proc);
+        asm.assemble_file(&path)
+    }
+    else{
+        fs::read(&path)
+    };
     
-    if let Ok(lines) = read_lines("lib/hello.s"){        
-        for l in lines{
-            let ml = l.unwrap();
-            match asm.read_line(ml.as_str()){
-                Some(values) => {
-                    for v in values{
-                        machine.push(v);
-                    }
-                },
-                None => ()
-            }
+    let image = match image{
+        Ok(image) => image,
+        Err(e) => {
+            println!("Could not read {}: {}", path, e);
+            return;
         }
-    }
+    };
     
-    for i in 0..machine.len(){
-        match proc.run(machine[i]){
-            Err(e) => {
-                println!("Got error on line {}: {:#2x}: {:?}", i, machine[i], e);
-            },
-            _ => ()
+    let range = match proc.load_image(&image, &config){
+        Ok(range) => range,
+        Err(e) => {
+            println!("Could not load {}: {:?}", path, e);
+            return;
         }
+    };
+    
+    if let Err(e) = proc.run_until(|p| p.program_counter() == range.end, MAX_STEPS){
+        println!("Got error {}", e);
     }
 }
@@ -1,93 +1,352 @@
-use std::borrow::BorrowMut;
+use std::cell::Cell;
 use std::collections::HashMap;
 use std::io::{stdout, Write};
-use std::ops::{Index, IndexMut};
-use std::rc::Rc;
-use std::cell::RefCell;
 use std::sync::{Arc, Mutex, MutexGuard};
-/*
-const LINK0_OUTPUT: i32 = 0x8000_0000;
-const LINK1_OUTPUT: i32 = 0x8000_0004;
-const LINK2_OUTPUT: i32 = 0x8000_0008;
-const LINK3_OUTPUT: i32 = 0x8000_000C;
-const LINK0_INPUT: i32  = 0x8000_0010;
-const LINK1_INPUT: i32  = 0x8000_0014;
-const LINK2_INPUT: i32  = 0x8000_0018;
-const LINK3_INPUT: i32  = 0x8000_001C;
-const EVENT_CHANNEL: i32= 0x8000_0020;
-const HIGH_POINTER: i32 = 0x8000_0024;
-const LOW_POINTER: i32  = 0x8000_0028;
-const LOW_WORKSPACE:i32 = 0x8000_002C;
-*/
-pub const REG_BASE: i32 = 0x1000_0000u32 as i32;
-pub const CLOCK_REG_0: i32 = REG_BASE + 0x00;
-pub const CLOCK_REG_1: i32 = REG_BASE + 0x04;
-pub const FRONT_PTR_0: i32 = REG_BASE + 0x08;
-pub const FRONT_PTR_1: i32 = REG_BASE + 0x0C;
-pub const BACK_PTR_0: i32  = REG_BASE + 0x10;
-pub const BACK_PTR_1: i32  = REG_BASE + 0x14;
-
-pub const REGISTER_CACHE: i32 = 0x8000_002Cu32 as i32;
 
 pub const TERMINAL_OUT: i32 = 0x0001_0000;
 
+/// Start of on-chip RAM, transputer addresses are signed
+pub const MOST_NEG: i32 = i32::MIN;
+
+/// Link output channels, link n is the word at LINK_OUTPUT + 4n
+pub const LINK_OUTPUT: i32 = MOST_NEG;
+/// Link input channels, link n is the word at LINK_INPUT + 4n
+pub const LINK_INPUT: i32 = MOST_NEG + 0x10;
+pub const EVENT_CHANNEL: i32 = MOST_NEG + 0x20;
+/// Timer queue front pointers, high then low priority (TPtrLoc0/1)
+pub const TPTR_LOC: i32 = MOST_NEG + 0x24;
+/// Wdesc, Iptr, A, B, C, Status and E of a low priority process
+/// interrupted by a high priority one, Wdesc is NotProcess.p if there is none
+pub const REGISTER_CACHE: i32 = MOST_NEG + 0x2C;
+/// First word after those reserved by every model, the lowest MemStart
+pub const RESERVED_END: i32 = MOST_NEG + 0x48;
+
+/// Default size of external memory, only pages that are written take up space
 pub const DRAM_SIZE: usize = 1024*1024*128*2;
 
+/// Where RAM is in the address space, anything else is out of range.
+/// The reserved words below MemStart are the start of on-chip RAM, the
+/// processor keeps its channels, timer queues and interrupted registers there.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct MemMap{
+    /// Bytes of on-chip RAM from MostNeg, it takes precedence over external memory
+    pub on_chip: u32,
+    /// First address of external memory
+    pub external_start: i32,
+    /// Bytes of external memory
+    pub external_size: u32
+}
+
+/// Memory access failure
+#[derive(Debug, Clone, PartialEq)]
+pub enum MemErr{
+    /// Word access to an address that is not a multiple of 4
+    Unaligned(i32),
+    /// Address outside of the memory map
+    OutOfRange(i32)
+}
+
+/// Bytes in a page of memory
+pub const PAGE_SIZE: usize = 4096;
+
+/// RAM allocated a page at a time when first written, untouched memory reads as 0
+#[derive(Default)]
+struct Pages{
+    pages: HashMap<u32, Box<[u8; PAGE_SIZE]>>
+}
+
+impl Pages{
+    /// Page number of address and where in the page it is
+    fn locate(address: i32) -> (u32, usize){
+        let a = address as u32;
+        (a / PAGE_SIZE as u32, a as usize % PAGE_SIZE)
+    }
+    
+    /// Fill buf from the bytes starting at address
+    fn read(&self, address: i32, buf: &mut [u8]){
+        let mut done = 0;
+        while done < buf.len(){
+            let (page, offset) = Self::locate(address.wrapping_add(done as i32));
+            let n = (PAGE_SIZE - offset).min(buf.len() - done);
+            match self.pages.get(&page){
+                Some(p) => buf[done..done + n].copy_from_slice(&p[offset..offset + n]),
+                None => buf[done..done + n].fill(0)
+            }
+            done += n;
+        }
+    }
+    
+    /// Write data to the bytes starting at address
+    fn write(&mut self, address: i32, data: &[u8]){
+        let mut done = 0;
+        while done < data.len(){
+            let (page, offset) = Self::locate(address.wrapping_add(done as i32));
+            let n = (PAGE_SIZE - offset).min(data.len() - done);
+            let p = self.pages.entry(page).or_insert_with(|| Box::new([0; PAGE_SIZE]));
+            p[offset..offset + n].copy_from_slice(&data[done..done + n]);
+            done += n;
+        }
+    }
+    
+    /// Bytes of the page holding the byte before end, up to end
+    fn before(end: i32) -> usize{
+        Self::locate(end.wrapping_sub(1)).1 + 1
+    }
+    
+    /// Copy len bytes from source to dest for which keep is true, a piece
+    /// within one source and one destination page at a time. Overlapping
+    /// blocks are copied as if through a buffer.
+    fn copy_if<F: Fn(u8) -> bool>(&mut self, source: i32, dest: i32, len: usize, keep: F){
+        let mut buf = [0; PAGE_SIZE];
+        // Start from the end when dest overlaps the end of source
+        let backwards = (dest.wrapping_sub(source) as u32 as usize) < len;
+        let mut done = 0;
+        while done < len{
+            let remaining = len - done;
+            let (from, to, n) = if backwards{
+                let from_end = source.wrapping_add(remaining as i32);
+                let to_end = dest.wrapping_add(remaining as i32);
+                let n = Self::before(from_end).min(Self::before(to_end)).min(remaining);
+                (from_end.wrapping_sub(n as i32), to_end.wrapping_sub(n as i32), n)
+            }
+            else{
+                let from = source.wrapping_add(done as i32);
+                let to = dest.wrapping_add(done as i32);
+                let n = (PAGE_SIZE - Self::locate(from).1).min(PAGE_SIZE - Self::locate(to).1).min(remaining);
+                (from, to, n)
+            };
+            self.copy_piece(from, to, &mut buf[..n], &keep);
+            done += n;
+        }
+    }
+    
+    /// Copy a piece within a single page each side through buf, an untouched
+    /// source onto an untouched destination is left alone as both read as 0
+    fn copy_piece<F: Fn(u8) -> bool>(&mut self, from: i32, to: i32, buf: &mut [u8], keep: &F){
+        let (source_page, source_offset) = Self::locate(from);
+        let (dest_page, dest_offset) = Self::locate(to);
+        match self.pages.get(&source_page){
+            Some(p) => buf.copy_from_slice(&p[source_offset..source_offset + buf.len()]),
+            None if !self.pages.contains_key(&dest_page) => return,
+            None => buf.fill(0)
+        }
+        let p = self.pages.entry(dest_page).or_insert_with(|| Box::new([0; PAGE_SIZE]));
+        for (d, v) in p[dest_offset..dest_offset + buf.len()].iter_mut().zip(buf.iter()){
+            if keep(*v){
+                *d = *v;
+            }
+        }
+    }
+}
+
 pub struct Mem{
-    contents: Arc<Mutex<Vec<u8>>>
+    contents: Arc<Mutex<Pages>>,
+    map: MemMap,
+    /// Accesses to external memory made through this handle, a block copy
+    /// counts each word read and written. Clones keep their own count, so the
+    /// processor is only charged for its own accesses.
+    external: Cell<u64>
 }
 
 impl Clone for Mem{
     fn clone(&self) -> Self {
         Self{
-            contents: self.contents.clone()
+            contents: self.contents.clone(),
+            map: self.map,
+            external: Cell::new(0)
         }
     }
 }
 
 impl Mem{
-    pub fn new(capacity: usize) -> Self{
-        let m = Mem{
-            contents: Arc::new(Mutex::new(vec![0; capacity]))
-        };
-        m
+    pub fn new(map: MemMap) -> Self{
+        Mem{
+            contents: Arc::new(Mutex::new(Pages::default())),
+            map,
+            external: Cell::new(0)
+        }
     }
     
-    fn get(&self) -> MutexGuard<'_, Vec<u8>>{
+    fn get(&self) -> MutexGuard<'_, Pages>{
         self.contents.lock().unwrap()
     }
     
-    pub fn write(&mut self, address: i32, value: i32){
+    pub fn map(&self) -> MemMap{
+        self.map
+    }
+    
+    /// First address of each page that has been written, in address order
+    pub fn touched_pages(&self) -> Vec<i32>{
+        let mut pages: Vec<i32> = self.get().pages.keys()
+            .map(|page| page.wrapping_mul(PAGE_SIZE as u32) as i32)
+            .collect();
+        pages.sort();
+        pages
+    }
+    
+    /// Address is in on-chip RAM
+    pub fn is_on_chip(&self, address: i32) -> bool{
+        (address.wrapping_sub(MOST_NEG) as u32) < self.map.on_chip
+    }
+    
+    /// Number of external memory accesses made through this handle so far
+    pub fn external_accesses(&self) -> u64{
+        self.external.get()
+    }
+    
+    /// Count accesses made to address, once they are known to be in range
+    fn count(&self, address: i32, accesses: u64){
+        if !self.is_on_chip(address){
+            self.external.set(self.external.get() + accesses);
+        }
+    }
+    
+    /// Words a block of len bytes at address spans
+    fn words(address: i32, len: u32) -> u64{
+        if len == 0{
+            return 0;
+        }
+        let first = address & !0b11;
+        let last = address.wrapping_add(len as i32 - 1) & !0b11;
+        (last.wrapping_sub(first) as u32 / 4) as u64 + 1
+    }
+    
+    /// Position of address in RAM, counting on-chip RAM first
+    fn offset(&self, address: i32) -> Option<usize>{
+        if self.is_on_chip(address){
+            return Some(address.wrapping_sub(MOST_NEG) as u32 as usize);
+        }
+        let external = address.wrapping_sub(self.map.external_start) as u32;
+        if external < self.map.external_size{
+            return Some(self.map.on_chip as usize + external as usize);
+        }
+        None
+    }
+    
+    /// The `len` bytes starting at address have to be contiguous RAM without
+    /// wrapping past MostPos
+    fn check(&self, address: i32, len: usize) -> Result<(), MemErr>{
+        let first = self.offset(address).ok_or(MemErr::OutOfRange(address))?;
+        if len <= 1{
+            return Ok(());
+        }
+        let last = i32::try_from(len - 1).ok()
+            .and_then(|n| address.checked_add(n))
+            .and_then(|a| self.offset(a));
+        match last{
+            Some(last) if last == first + len - 1 => Ok(()),
+            _ => Err(MemErr::OutOfRange(address))
+        }
+    }
+    
+    /// Block copies lock memory once, unless they include the terminal
+    fn writes_terminal(dest: i32, len: u32) -> bool{
+        (TERMINAL_OUT.wrapping_sub(dest) as u32) < len
+    }
+    
+    fn check_aligned(address: i32) -> Result<(), MemErr>{
+        if address % 4 != 0{
+            return Err(MemErr::Unaligned(address));
+        }
+        Ok(())
+    }
+    
+    pub fn write(&mut self, address: i32, value: i32) -> Result<(), MemErr>{
         // Write a word
-        assert!(address % 4 == 0);
-        for i in 0..4{
-            self.write_byte(address + i, ((value >> (8*i)) & 0xFF) as u8);
+        Self::check_aligned(address)?;
+        self.check(address, 4)?;
+        self.count(address, 1);
+        if address == TERMINAL_OUT{
+            for (i, v) in value.to_le_bytes().into_iter().enumerate(){
+                self.store_byte(address.wrapping_add(i as i32), v)?;
+            }
+            return Ok(());
+        }
+        self.get().write(address, &value.to_le_bytes());
+        Ok(())
+    }
+    
+    pub fn read(&self, address: i32) -> Result<i32, MemErr>{
+        Self::check_aligned(address)?;
+        self.check(address, 4)?;
+        self.count(address, 1);
+        let mut bytes = [0; 4];
+        self.get().read(address, &mut bytes);
+        Ok(i32::from_le_bytes(bytes))
+    }
+    
+    pub fn read_byte(&self, address: i32) -> Result<u8, MemErr>{
+        self.check(address, 1)?;
+        self.count(address, 1);
+        let mut byte = [0];
+        self.get().read(address, &mut byte);
+        Ok(byte[0])
+    }
+    
+    /// Write a block of bytes starting at address
+    pub fn write_bytes(&mut self, address: i32, values: &[u8]) -> Result<(), MemErr>{
+        for (i, v) in values.iter().enumerate(){
+            self.write_byte(address.wrapping_add(i as i32), *v)?;
         }
+        Ok(())
     }
     
-    pub fn read(&self, address: i32) -> i32{
-        assert!(address % 4 == 0);
-        let mut sum = 0;
-        for i in 0..4{
-            sum += (self.get()[(address+i) as usize] as i32) << (i*8);
+    /// Copy len bytes from source to dest, overlapping blocks are copied as if
+    /// through a buffer
+    pub fn copy(&mut self, source: i32, dest: i32, len: u32) -> Result<(), MemErr>{
+        if Self::writes_terminal(dest, len){
+            return self.copy_if(source, dest, len, |_| true);
         }
-        sum
+        self.check(source, len as usize)?;
+        self.check(dest, len as usize)?;
+        self.count(source, Self::words(source, len));
+        self.count(dest, Self::words(dest, len));
+        self.get().copy_if(source, dest, len as usize, |_| true);
+        Ok(())
     }
     
-    pub fn read_byte(&self, address: i32) -> u8{
-        return self.get()[address as usize]
+    /// Copy the bytes from source to dest for which keep is true, leaving the
+    /// rest of dest alone
+    pub fn copy_if<F: Fn(u8) -> bool>(&mut self, source: i32, dest: i32, len: u32, keep: F) -> Result<(), MemErr>{
+        self.check(source, len as usize)?;
+        if Self::writes_terminal(dest, len){
+            // The terminal is written a byte at a time
+            self.count(source, Self::words(source, len));
+            for i in 0..len as i32{
+                let mut v = [0];
+                self.get().read(source.wrapping_add(i), &mut v);
+                if keep(v[0]){
+                    self.store_byte(dest.wrapping_add(i), v[0])?;
+                }
+            }
+            self.count(dest, Self::words(dest, len));
+            return Ok(());
+        }
+        self.check(dest, len as usize)?;
+        self.count(source, Self::words(source, len));
+        self.count(dest, Self::words(dest, len));
+        self.get().copy_if(source, dest, len as usize, keep);
+        Ok(())
     }
     
-    pub fn write_byte(&mut self, address: i32, value: u8){
+    pub fn write_byte(&mut self, address: i32, value: u8) -> Result<(), MemErr>{
+        self.store_byte(address, value)?;
+        self.count(address, 1);
+        Ok(())
+    }
+    
+    /// Write a byte without counting the access
+    fn store_byte(&mut self, address: i32, value: u8) -> Result<(), MemErr>{
         match address{
             TERMINAL_OUT => {
                 let _ = stdout().write(&[value]);
             },
             _ => {
-                self.get()[address as usize] = value;
+                self.check(address, 1)?;
+                self.get().write(address, &[value]);
             }
         }
-        
+        Ok(())
     }
 }
 
@@ -97,6 +356,12 @@ pub struct Stack{
     reg: [i32; STACK_SIZE]
 }
 
+impl Default for Stack{
+    fn default() -> Self{
+        Self::new()
+    }
+}
+
 impl Stack{
     pub fn new() -> Self{
         Self{
@@ -123,9 +388,7 @@ impl Stack{
     pub fn swap(&mut self){
         // Swap A and B registers
         let c = &mut self.reg;
-        let a = c[0];
-        c[0] = c[1];
-        c[1] = a;
+        c.swap(0, 1);
     }
     
     /// Register A
@@ -0,0 +1,610 @@
+/* Floating point unit, the FA/FB/FC register stack and its instructions */
+
+use super::{ATYPE, OpErr, OpResult, OpVal, Proc, ProcLibrary};
+use super::rounding::{self, Ieee, RoundMode};
+
+/// Contents of a floating point register, single or double length
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Real{
+    Single(f32),
+    Double(f64)
+}
+
+impl Real{
+    /// Value widened to double length, exact for singles
+    pub fn to_f64(self) -> f64{
+        match self{
+            Real::Single(v) => v as f64,
+            Real::Double(v) => v
+        }
+    }
+
+    pub fn to_f32(self) -> f32{
+        match self{
+            Real::Single(v) => v,
+            Real::Double(v) => v as f32
+        }
+    }
+
+    pub fn is_nan(self) -> bool{
+        self.to_f64().is_nan()
+    }
+
+    pub fn is_finite(self) -> bool{
+        self.to_f64().is_finite()
+    }
+
+    /// Apply f keeping the length of the register
+    fn map(self, single: impl Fn(f32) -> f32, double: impl Fn(f64) -> f64) -> Real{
+        match self{
+            Real::Single(v) => Real::Single(single(v)),
+            Real::Double(v) => Real::Double(double(v))
+        }
+    }
+}
+
+impl Default for Real{
+    fn default() -> Self{
+        Real::Single(0.0)
+    }
+}
+
+pub const FLOAT_STACK_SIZE: usize = 3;
+
+/// FA, FB and FC
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct FloatStack{
+    reg: [Real; FLOAT_STACK_SIZE]
+}
+
+impl FloatStack{
+    pub fn new() -> Self{
+        Self{
+            reg: [Real::default(); FLOAT_STACK_SIZE]
+        }
+    }
+
+    pub fn push(&mut self, value: Real){
+        self.reg[2] = self.reg[1];
+        self.reg[1] = self.reg[0];
+        self.reg[0] = value;
+    }
+
+    pub fn pop(&mut self) -> Real{
+        let v = self.reg[0];
+        self.reg[0] = self.reg[1];
+        self.reg[1] = self.reg[2];
+        v
+    }
+
+    /// Register FA
+    pub fn a(&self) -> Real{
+        self.reg[0]
+    }
+
+    /// Register FB
+    pub fn b(&self) -> Real{
+        self.reg[1]
+    }
+
+    /// Get register value via index
+    /// 0 - FA, 1 - FB, 2 - FC
+    pub fn get(&self, index: usize) -> Real{
+        self.reg[index]
+    }
+
+    /// Set register via index
+    pub fn set(&mut self, index: usize, value: Real){
+        self.reg[index] = value;
+    }
+}
+
+/// Rounded arithmetic operations
+#[derive(Clone, Copy)]
+enum Arith{
+    Add,
+    Sub,
+    Mul,
+    Div
+}
+
+impl Arith{
+    fn apply<T: Ieee>(self, b: T, a: T, mode: RoundMode) -> T{
+        match self{
+            Arith::Add => rounding::add(b, a, mode),
+            Arith::Sub => rounding::sub(b, a, mode),
+            Arith::Mul => rounding::mul(b, a, mode),
+            Arith::Div => rounding::div(b, a, mode)
+        }
+    }
+}
+
+/// b op a, in single length if both are single and double length otherwise
+fn combine(b: Real, a: Real, op: Arith, mode: RoundMode) -> Real{
+    match (b, a){
+        (Real::Single(b), Real::Single(a)) => Real::Single(op.apply(b, a, mode)),
+        (b, a) => Real::Double(op.apply(b.to_f64(), a.to_f64(), mode))
+    }
+}
+
+/// b op a rounded in mode, and rounded to nearest so an overflow shows as
+/// an infinity whatever the mode
+fn combine_checked(b: Real, a: Real, op: Arith, mode: RoundMode) -> (Real, Real){
+    (combine(b, a, op, mode), combine(b, a, op, RoundMode::Nearest))
+}
+
+/// FA = FB op FA, popping the stack
+fn arithmetic(p: &mut Proc, op: Arith) -> OpResult{
+    let mode = p.take_round_mode();
+    let a = p.fstack.pop();
+    let b = p.fstack.pop();
+    let (r, nearest) = combine_checked(b, a, op, mode);
+    p.check_reals(&[b, a, r, nearest]);
+    p.fstack.push(r);
+    Ok(OpVal::Null)
+}
+
+/// FA = FA op the value at address A
+fn load_and_operate(p: &mut Proc, double_length: bool, op: Arith) -> OpResult{
+    let mode = p.take_round_mode();
+    let a = p.stack.pop();
+    let v = p.read_real(a, double_length)?;
+    let fa = p.fstack.a();
+    let (r, nearest) = combine_checked(fa, v, op, mode);
+    p.check_reals(&[fa, v, r, nearest]);
+    p.fstack.set(0, r);
+    Ok(OpVal::Null)
+}
+
+/// FA = FA op factor, in the length of FA
+fn scale(p: &mut Proc, op: Arith, factor: f64) -> OpResult{
+    let mode = p.take_round_mode();
+    let fa = p.fstack.a();
+    let (r, nearest) = combine_checked(fa, fa.map(|_| factor as f32, |_| factor), op, mode);
+    p.check_reals(&[fa, r, nearest]);
+    p.fstack.set(0, r);
+    Ok(OpVal::Null)
+}
+
+/// Starting point for the square root iteration, a straight line through
+/// the root at both ends of the mantissa range, within 6%
+fn sqrt_estimate<T: Ieee>(v: T) -> T{
+    if !v.is_finite() || v <= T::ZERO{
+        return v;
+    }
+    let (m, e) = v.frexp();
+    let (m, e) = if e % 2 != 0 { (m + m, e - 1) } else { (m, e) };
+    let two = T::pow2(1);
+    (m + two) / (two + T::pow2(0)) * T::pow2(e / 2)
+}
+
+/// One Newton-Raphson step towards the square root of v
+fn sqrt_newton<T: Ieee>(g: T, v: T) -> T{
+    if !g.is_finite() || g <= T::ZERO{
+        return g;
+    }
+    (g + v / g) * T::pow2(-1)
+}
+
+/// Bits of quotient removed by each partial remainder step
+const REM_STEP_BITS: i32 = 32;
+
+/// One step of FB rem FA. A is 1 once FA holds the remainder, otherwise
+/// FB holds a partial remainder with the same result and FA is unchanged.
+fn remainder_step(p: &mut Proc) -> OpResult{
+    let d = p.fstack.a();
+    let v = p.fstack.b();
+    let (x, y) = (v.to_f64(), d.to_f64());
+    if x.is_finite() && y.is_finite() && x != 0.0 && y != 0.0{
+        let shift = x.frexp().1 - y.frexp().1 - REM_STEP_BITS;
+        if shift > 0{
+            // Taking off a multiple of 2y keeps the parity of the quotient
+            let partial = x % rounding::ldexp(y.abs(), shift);
+            p.fstack.set(1, v.map(|_| partial as f32, |_| partial));
+            return Ok(OpVal::Int(0));
+        }
+    }
+    p.fstack.pop();
+    p.fstack.pop();
+    let r = rounding::remainder(x, y);
+    let r = match (v, d){
+        (Real::Single(_), Real::Single(_)) => Real::Single(r as f32),
+        _ => Real::Double(r)
+    };
+    p.check_reals(&[v, d, r]);
+    p.fstack.push(r);
+    Ok(OpVal::Int(1))
+}
+
+/// Set FA to f(FA)
+fn unary(p: &mut Proc, single: impl Fn(f32) -> f32, double: impl Fn(f64) -> f64) -> OpResult{
+    p.fstack.set(0, p.fstack.a().map(single, double));
+    Ok(OpVal::Null)
+}
+
+/// Set FP_Error unless FA is within min..=max
+fn check_range(p: &mut Proc, min: f64, max: f64){
+    let v = p.fstack.a().to_f64();
+    if !(min..=max).contains(&v){
+        p.fp_error = true;
+    }
+}
+
+/// Round FA to an integer valued real in the current rounding mode
+fn round_to_int(p: &mut Proc){
+    let mode = p.take_round_mode();
+    let fa = p.fstack.a();
+    p.fstack.set(0, fa.map(|v| rounding::int(v, mode), |v| rounding::int(v, mode)));
+    p.check_reals(&[fa]);
+}
+
+impl Proc{
+    /// Read a single or double length real, low word first
+    pub(super) fn read_real(&self, address: ATYPE, double: bool) -> Result<Real, OpErr>{
+        let lo = self.mem.read(address)? as u32;
+        if !double{
+            return Ok(Real::Single(f32::from_bits(lo)));
+        }
+        let hi = self.mem.read(address.wrapping_add(4))? as u32;
+        Ok(Real::Double(f64::from_bits(((hi as u64) << 32) | lo as u64)))
+    }
+
+    pub(super) fn write_real(&mut self, address: ATYPE, v: Real) -> Result<(), OpErr>{
+        match v{
+            Real::Single(v) => self.mem.write(address, v.to_bits() as i32)?,
+            Real::Double(v) => {
+                let bits = v.to_bits();
+                self.mem.write(address, bits as u32 as i32)?;
+                self.mem.write(address.wrapping_add(4), (bits >> 32) as u32 as i32)?;
+            }
+        }
+        Ok(())
+    }
+
+    /// The rounding mode applies to one operation, then reverts to nearest
+    fn take_round_mode(&mut self) -> RoundMode{
+        std::mem::take(&mut self.round_mode)
+    }
+
+    /// Infinities and NaNs, as operands or results, set FP_Error. Pass the
+    /// round to nearest result too, so overflow is caught in every mode.
+    fn check_reals(&mut self, values: &[Real]){
+        if values.iter().any(|v| !v.is_finite()){
+            self.fp_error = true;
+        }
+    }
+
+    /// Check the floating point error flag
+    pub fn fp_error_flag(&self) -> bool{
+        self.fp_error
+    }
+
+    /// Get the floating point register stack, FA first
+    pub fn get_float_stack(&self) -> Vec<Real>{
+        (0..FLOAT_STACK_SIZE).map(|i| self.fstack.get(i)).collect()
+    }
+}
+
+/// Floating point instructions, including those reached through fpentry
+pub fn define_fpu(pl: &mut ProcLibrary){
+    // Floating point escape, A selects the operation
+    pl.define_indirect("fpentry", |p|{
+        let a = p.stack.pop();
+        match p.library.get_fpentry(a as usize){
+            Some(f) => f.clone()(p),
+            None => Err(OpErr::IllegalOpcode)
+        }
+    });
+
+    // Loads
+    pl.define_indirect("fpldnlsn", |p|{
+        let a = p.stack.pop();
+        let v = p.read_real(a, false)?;
+        p.fstack.push(v);
+        Ok(OpVal::Null)
+    });
+
+    pl.define_indirect("fpldnldb", |p|{
+        let a = p.stack.pop();
+        let v = p.read_real(a, true)?;
+        p.fstack.push(v);
+        Ok(OpVal::Null)
+    });
+
+    // Indexed loads, A is the base and B the index
+    pl.define_indirect("fpldnlsni", |p|{
+        let a = p.stack.pop();
+        let b = p.stack.pop();
+        let v = p.read_real(a.wrapping_add(b << 2), false)?;
+        p.fstack.push(v);
+        Ok(OpVal::Null)
+    });
+
+    pl.define_indirect("fpldnldbi", |p|{
+        let a = p.stack.pop();
+        let b = p.stack.pop();
+        let v = p.read_real(a.wrapping_add(b << 3), true)?;
+        p.fstack.push(v);
+        Ok(OpVal::Null)
+    });
+
+    pl.define_indirect("fpldzerosn", |p|{
+        p.fstack.push(Real::Single(0.0));
+        Ok(OpVal::Null)
+    });
+
+    pl.define_indirect("fpldzerodb", |p|{
+        p.fstack.push(Real::Double(0.0));
+        Ok(OpVal::Null)
+    });
+
+    // Stores
+    pl.define_indirect("fpstnlsn", |p|{
+        let a = p.stack.pop();
+        let v = p.fstack.pop();
+        p.write_real(a, Real::Single(v.to_f32()))?;
+        Ok(OpVal::Null)
+    });
+
+    pl.define_indirect("fpstnldb", |p|{
+        let a = p.stack.pop();
+        let v = p.fstack.pop();
+        p.write_real(a, Real::Double(v.to_f64()))?;
+        Ok(OpVal::Null)
+    });
+
+    // Arithmetic
+    pl.define_indirect("fpadd", |p|{
+        arithmetic(p, Arith::Add)
+    });
+
+    pl.define_indirect("fpsub", |p|{
+        arithmetic(p, Arith::Sub)
+    });
+
+    pl.define_indirect("fpmul", |p|{
+        arithmetic(p, Arith::Mul)
+    });
+
+    pl.define_indirect("fpdiv", |p|{
+        arithmetic(p, Arith::Div)
+    });
+
+    // Load from memory and operate on FA
+    pl.define_indirect("fpldnladdsn", |p|{
+        load_and_operate(p, false, Arith::Add)
+    });
+
+    pl.define_indirect("fpldnladddb", |p|{
+        load_and_operate(p, true, Arith::Add)
+    });
+
+    pl.define_indirect("fpldnlmulsn", |p|{
+        load_and_operate(p, false, Arith::Mul)
+    });
+
+    pl.define_indirect("fpldnlmuldb", |p|{
+        load_and_operate(p, true, Arith::Mul)
+    });
+
+    // Stack manipulation
+    pl.define_indirect("fprev", |p|{
+        let a = p.fstack.pop();
+        let b = p.fstack.pop();
+        p.fstack.push(a);
+        p.fstack.push(b);
+        Ok(OpVal::Null)
+    });
+
+    pl.define_indirect("fpdup", |p|{
+        p.fstack.push(p.fstack.a());
+        Ok(OpVal::Null)
+    });
+
+    // Comparisons, the result goes to A
+    pl.define_indirect("fpgt", |p|{
+        let a = p.fstack.pop();
+        let b = p.fstack.pop();
+        Ok(OpVal::Int((b.to_f64() > a.to_f64()) as i32))
+    });
+
+    pl.define_indirect("fpeq", |p|{
+        let a = p.fstack.pop();
+        let b = p.fstack.pop();
+        Ok(OpVal::Int((b.to_f64() == a.to_f64()) as i32))
+    });
+
+    pl.define_indirect("fpordered", |p|{
+        Ok(OpVal::Int(!(p.fstack.a().is_nan() || p.fstack.b().is_nan()) as i32))
+    });
+
+    pl.define_indirect("fpnan", |p|{
+        Ok(OpVal::Int(p.fstack.a().is_nan() as i32))
+    });
+
+    pl.define_indirect("fpnotfinite", |p|{
+        Ok(OpVal::Int(!p.fstack.a().is_finite() as i32))
+    });
+
+    // fpentry operations
+    pl.define_fpentry("fpuabs", |p|{
+        unary(p, f32::abs, f64::abs)
+    });
+
+    pl.define_fpentry("fpumulby2", |p|{
+        scale(p, Arith::Mul, 2.0)
+    });
+
+    pl.define_fpentry("fpudivby2", |p|{
+        scale(p, Arith::Div, 2.0)
+    });
+
+    pl.define_fpentry("fpuexpinc32", |p|{
+        scale(p, Arith::Mul, 4294967296.0)
+    });
+
+    pl.define_fpentry("fpuexpdec32", |p|{
+        scale(p, Arith::Div, 4294967296.0)
+    });
+
+    // Rounding modes, each applies to the next rounding operation only
+    pl.define_fpentry("fpurn", |p|{
+        p.round_mode = RoundMode::Nearest;
+        Ok(OpVal::Null)
+    });
+
+    pl.define_fpentry("fpurz", |p|{
+        p.round_mode = RoundMode::Zero;
+        Ok(OpVal::Null)
+    });
+
+    pl.define_fpentry("fpurp", |p|{
+        p.round_mode = RoundMode::Plus;
+        Ok(OpVal::Null)
+    });
+
+    pl.define_fpentry("fpurm", |p|{
+        p.round_mode = RoundMode::Minus;
+        Ok(OpVal::Null)
+    });
+
+    // FP_Error
+    pl.define_fpentry("fpuseterr", |p|{
+        p.fp_error = true;
+        Ok(OpVal::Null)
+    });
+
+    pl.define_fpentry("fpuclrerr", |p|{
+        p.fp_error = false;
+        Ok(OpVal::Null)
+    });
+
+    // Pass FP_Error on to the processor error flag
+    pl.define_indirect("fpchkerr", |p|{
+        if p.fp_error{
+            p.throw_error(OpErr::FloatingPoint);
+        }
+        Ok(OpVal::Null)
+    });
+
+    // A = not FP_Error, then clear it
+    pl.define_indirect("fptesterr", |p|{
+        let err = p.fp_error;
+        p.fp_error = false;
+        Ok(OpVal::Int(if err { 0 } else { 1 }))
+    });
+
+    // Integer to real, loading the integer from address A
+    pl.define_indirect("fpi32tor32", |p|{
+        let mode = p.take_round_mode();
+        let a = p.stack.pop();
+        let v = p.mem.read(a)?;
+        p.fstack.push(Real::Single(rounding::int_to_single(v, mode)));
+        Ok(OpVal::Null)
+    });
+
+    pl.define_indirect("fpi32tor64", |p|{
+        let a = p.stack.pop();
+        let v = p.mem.read(a)?;
+        p.fstack.push(Real::Double(v as f64));
+        Ok(OpVal::Null)
+    });
+
+    // Unsigned 32 bit integer
+    pl.define_indirect("fpb32tor64", |p|{
+        let a = p.stack.pop();
+        let v = p.mem.read(a)? as u32;
+        p.fstack.push(Real::Double(v as f64));
+        Ok(OpVal::Null)
+    });
+
+    // Real to integer
+    pl.define_indirect("fpint", |p|{
+        round_to_int(p);
+        Ok(OpVal::Null)
+    });
+
+    pl.define_indirect("fprtoi32", |p|{
+        round_to_int(p);
+        check_range(p, i32::MIN as f64, i32::MAX as f64);
+        Ok(OpVal::Null)
+    });
+
+    // Store the integer held in FA at address A
+    pl.define_indirect("fpstnli32", |p|{
+        let a = p.stack.pop();
+        let v = p.fstack.pop();
+        p.mem.write(a, v.to_f64() as i64 as i32)?;
+        Ok(OpVal::Null)
+    });
+
+    pl.define_fpentry("fpuchki32", |p|{
+        check_range(p, i32::MIN as f64, i32::MAX as f64);
+        Ok(OpVal::Null)
+    });
+
+    pl.define_fpentry("fpuchki64", |p|{
+        // The largest double below 2^63
+        check_range(p, i64::MIN as f64, (i64::MAX as f64).next_down());
+        Ok(OpVal::Null)
+    });
+
+    // Length conversions
+    pl.define_fpentry("fpur32tor64", |p|{
+        let fa = p.fstack.a();
+        p.fstack.set(0, Real::Double(fa.to_f64()));
+        Ok(OpVal::Null)
+    });
+
+    pl.define_fpentry("fpur64tor32", |p|{
+        let mode = p.take_round_mode();
+        let fa = p.fstack.a();
+        let r = Real::Single(rounding::narrow(fa.to_f64(), mode));
+        let nearest = Real::Single(rounding::narrow(fa.to_f64(), RoundMode::Nearest));
+        p.check_reals(&[fa, r, nearest]);
+        p.fstack.set(0, r);
+        Ok(OpVal::Null)
+    });
+
+    // Square root: fpusqrtfirst, fpusqrtstep twice for single length or five
+    // times for double length, then fpusqrtlast. FB and FC hold the argument
+    // throughout and FA the approximation, their previous contents are lost.
+    pl.define_fpentry("fpusqrtfirst", |p|{
+        let v = p.fstack.a();
+        p.fstack.set(1, v);
+        p.fstack.set(2, v);
+        p.fstack.set(0, v.map(sqrt_estimate, sqrt_estimate));
+        Ok(OpVal::Null)
+    });
+
+    pl.define_fpentry("fpusqrtstep", |p|{
+        let g = p.fstack.a();
+        let v = p.fstack.b();
+        p.fstack.set(0, v.map(|v| sqrt_newton(g.to_f32(), v), |v| sqrt_newton(g.to_f64(), v)));
+        Ok(OpVal::Null)
+    });
+
+    // Finishes from the approximation in FA, correctly rounded after the
+    // full number of steps
+    pl.define_fpentry("fpusqrtlast", |p|{
+        let mode = p.take_round_mode();
+        let g = p.fstack.a();
+        let v = p.fstack.b();
+        let r = v.map(|v| rounding::sqrt_from(v, g.to_f32(), mode), |v| rounding::sqrt_from(v, g.to_f64(), mode));
+        p.check_reals(&[v, r]);
+        p.fstack.set(0, r);
+        Ok(OpVal::Null)
+    });
+
+    // Remainder: fpremfirst, then fpremstep until A is 1
+    pl.define_indirect("fpremfirst", remainder_step);
+
+    pl.define_indirect("fpremstep", remainder_step);
+
+    // Double to single length by truncation, used in range reduction
+    pl.define_fpentry("fpunoround", |p|{
+        let fa = p.fstack.a();
+        p.fstack.set(0, Real::Single(rounding::narrow(fa.to_f64(), RoundMode::Zero)));
+        Ok(OpVal::Null)
+    });
+}
@@ -0,0 +1,286 @@
+/* Instruction metadata
+
+   One entry per instruction, used by the processor to decide which
+   operations a model has and what they cost, and by the assembler,
+   disassembler and debugger to name them. Stack effects count the integer
+   register stack only, operations on the floating point stack show 0. */
+
+use super::ModelDescriptor;
+
+/// How an instruction is encoded
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Encoding{
+    /// Function code in the top nibble of the instruction byte
+    Direct,
+    /// Operation code given to opr
+    Indirect,
+    /// Code loaded into A before fpentry
+    FpEntry
+}
+
+/// Which models have an instruction
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Availability{
+    All,
+    Extended,
+    BlockAndBitOps,
+    SoftFloat,
+    Fpu
+}
+
+impl Availability{
+    pub fn on(self, model: &ModelDescriptor) -> bool{
+        match self{
+            Availability::All => true,
+            Availability::Extended => model.extended,
+            Availability::BlockAndBitOps => model.block_and_bit_ops,
+            Availability::SoftFloat => model.soft_float,
+            Availability::Fpu => model.fpu
+        }
+    }
+}
+
+/// Description of a single instruction
+#[derive(Debug, Clone, PartialEq)]
+pub struct InstructionInfo{
+    pub mnemonic: &'static str,
+    pub encoding: Encoding,
+    pub code: usize,
+    /// Values taken from the register stack
+    pub pops: u8,
+    /// Values left on the register stack
+    pub pushes: u8,
+    /// Datasheet cycles, the fixed part for operations whose time
+    /// depends on their operands
+    pub cycles: u64,
+    pub availability: Availability,
+    pub description: &'static str
+}
+
+impl InstructionInfo{
+    /// An instruction on every model, taking one cycle and leaving the stack alone
+    const fn new(mnemonic: &'static str, encoding: Encoding, code: usize) -> Self{
+        InstructionInfo{ mnemonic, encoding, code, pops: 0, pushes: 0, cycles: 1, availability: Availability::All, description: "" }
+    }
+
+    const fn stack(self, pops: u8, pushes: u8) -> Self{
+        InstructionInfo{ pops, pushes, ..self }
+    }
+
+    const fn cycles(self, cycles: u64) -> Self{
+        InstructionInfo{ cycles, ..self }
+    }
+
+    const fn on(self, availability: Availability) -> Self{
+        InstructionInfo{ availability, ..self }
+    }
+
+    const fn about(self, description: &'static str) -> Self{
+        InstructionInfo{ description, ..self }
+    }
+}
+
+const fn direct(mnemonic: &'static str, code: usize) -> InstructionInfo{
+    InstructionInfo::new(mnemonic, Encoding::Direct, code)
+}
+
+const fn indirect(mnemonic: &'static str, code: usize) -> InstructionInfo{
+    InstructionInfo::new(mnemonic, Encoding::Indirect, code)
+}
+
+const fn fpentry(mnemonic: &'static str, code: usize) -> InstructionInfo{
+    InstructionInfo::new(mnemonic, Encoding::FpEntry, code)
+}
+
+use Availability::*;
+
+/// Direct instructions, indexed by function code
+pub static DIRECT_INSTRUCTIONS: [InstructionInfo; 16] = [
+    direct("j", 0x0).cycles(3).about("Jump relative to the next instruction"),
+    direct("ldlp", 0x1).stack(0, 1).about("Load the address of a workspace word"),
+    direct("pfix", 0x2).about("Prefix the operand of the next instruction"),
+    direct("ldnl", 0x3).stack(1, 1).cycles(2).about("Load the word at a word offset from A"),
+    direct("ldc", 0x4).stack(0, 1).about("Load constant"),
+    direct("ldnlp", 0x5).stack(1, 1).about("Add a word offset to the pointer in A"),
+    direct("nfix", 0x6).about("Negative prefix"),
+    direct("ldl", 0x7).stack(0, 1).cycles(2).about("Load a workspace word"),
+    direct("adc", 0x8).stack(1, 1).about("Add constant, with overflow checking"),
+    direct("call", 0x9).stack(3, 1).cycles(7).about("Save A, B, C and Iptr below the workspace and jump"),
+    direct("cj", 0xA).stack(1, 0).cycles(2).about("Jump if A is zero, otherwise pop A"),
+    direct("ajw", 0xB).about("Move the workspace pointer by a number of words"),
+    direct("eqc", 0xC).stack(1, 1).cycles(2).about("Compare A with a constant"),
+    direct("stl", 0xD).stack(1, 0).about("Store into a workspace word"),
+    direct("stnl", 0xE).stack(2, 0).cycles(2).about("Store B at a word offset from A"),
+    direct("opr", 0xF).about("Execute the operation selected by the operand")
+];
+
+/// Operations executed through opr, in opcode order
+pub static INDIRECT_INSTRUCTIONS: [InstructionInfo; 136] = [
+    indirect("rev", 0x00).stack(2, 2).about("Swap A and B"),
+    indirect("lb", 0x01).stack(1, 1).cycles(5).about("Load the byte A points to"),
+    indirect("bsub", 0x02).stack(2, 1).about("Byte subscript, A + B"),
+    indirect("endp", 0x03).stack(1, 0).cycles(13).about("End one of the processes of a parallel"),
+    indirect("diff", 0x04).stack(2, 1).about("B - A without overflow checking"),
+    indirect("add", 0x05).stack(2, 1).about("B + A with overflow checking"),
+    indirect("gcall", 0x06).stack(1, 1).cycles(4).about("Swap A and Iptr"),
+    indirect("in", 0x07).stack(3, 0).cycles(18).about("Input A bytes from channel B to C"),
+    indirect("prod", 0x08).stack(2, 1).cycles(4).about("B * A without overflow checking"),
+    indirect("gt", 0x09).stack(2, 1).cycles(2).about("B > A"),
+    indirect("wsub", 0x0A).stack(2, 1).cycles(2).about("Word subscript, A + 4B"),
+    indirect("out", 0x0B).stack(3, 0).cycles(20).about("Output A bytes from C to channel B"),
+    indirect("sub", 0x0C).stack(2, 1).about("B - A with overflow checking"),
+    indirect("startp", 0x0D).stack(2, 0).cycles(12).about("Start a process with workspace A at offset B"),
+    indirect("outbyte", 0x0E).stack(2, 0).cycles(23).about("Output the byte in B to channel A"),
+    indirect("outword", 0x0F).stack(2, 0).cycles(23).about("Output the word in B to channel A"),
+    indirect("seterr", 0x10).about("Set the error flag"),
+    indirect("resetch", 0x12).stack(1, 1).cycles(3).about("Reset channel A, leaving its old contents"),
+    indirect("csub0", 0x13).stack(2, 1).cycles(2).about("Set the error flag unless 0 <= B < A"),
+    indirect("stopp", 0x15).cycles(11).about("Stop the current process"),
+    indirect("ladd", 0x16).stack(3, 1).cycles(2).about("B + A + carry C with overflow checking"),
+    indirect("stlb", 0x17).stack(1, 0).about("Store the low priority back pointer"),
+    indirect("sthf", 0x18).stack(1, 0).about("Store the high priority front pointer"),
+    indirect("norm", 0x19).stack(2, 3).cycles(5).about("Normalise the double word B:A, count in C"),
+    indirect("ldiv", 0x1A).stack(3, 2).cycles(35).about("Divide the double word C:B by A"),
+    indirect("ldpi", 0x1B).stack(1, 1).cycles(2).about("Add Iptr to A"),
+    indirect("stlf", 0x1C).stack(1, 0).about("Store the low priority front pointer"),
+    indirect("xdble", 0x1D).stack(1, 2).cycles(2).about("Sign extend A to a double word"),
+    indirect("ldpri", 0x1E).stack(0, 1).about("Load the current priority"),
+    indirect("rem", 0x1F).stack(2, 1).cycles(37).about("Remainder of B / A"),
+    indirect("ret", 0x20).cycles(5).about("Return from call"),
+    indirect("lend", 0x21).stack(2, 0).cycles(10).about("Loop end, count and index at A"),
+    indirect("ldtimer", 0x22).stack(0, 1).cycles(2).about("Load the clock of the current priority"),
+    indirect("testerr", 0x29).stack(0, 1).cycles(2).about("Load and clear the error flag, true when clear"),
+    indirect("testpranal", 0x2A).stack(0, 1).cycles(2).about("Load whether analyse was asserted"),
+    indirect("tin", 0x2B).stack(1, 0).cycles(4).about("Wait until the clock is after A"),
+    indirect("div", 0x2C).stack(2, 1).cycles(39).about("B / A"),
+    indirect("dist", 0x2E).stack(3, 1).cycles(23).about("Disable a timer ALT guard"),
+    indirect("disc", 0x2F).stack(3, 1).cycles(8).about("Disable a channel ALT guard"),
+    indirect("diss", 0x30).stack(2, 1).cycles(4).about("Disable a skip ALT guard"),
+    indirect("lmul", 0x31).stack(3, 2).cycles(33).about("B * A + C as a double word"),
+    indirect("not", 0x32).stack(1, 1).about("Bitwise not"),
+    indirect("xor", 0x33).stack(2, 1).about("Bitwise exclusive or"),
+    indirect("bcnt", 0x34).stack(1, 1).cycles(2).about("Byte count, 4A"),
+    indirect("lshr", 0x35).stack(3, 2).cycles(3).about("Shift the double word C:B right by A"),
+    indirect("lshl", 0x36).stack(3, 2).cycles(3).about("Shift the double word C:B left by A"),
+    indirect("lsum", 0x37).stack(3, 2).cycles(3).about("B + A + carry C, leaving the carry"),
+    indirect("lsub", 0x38).stack(3, 1).cycles(2).about("B - A - borrow C with overflow checking"),
+    indirect("runp", 0x39).stack(1, 0).cycles(10).about("Schedule the process descriptor A"),
+    indirect("xword", 0x3A).stack(2, 1).cycles(4).about("Sign extend the part word B, A holds its sign bit"),
+    indirect("sb", 0x3B).stack(2, 0).cycles(4).about("Store the byte in B at A"),
+    indirect("gajw", 0x3C).stack(1, 1).cycles(2).about("Swap A and the workspace pointer"),
+    indirect("savel", 0x3D).stack(1, 0).cycles(4).about("Save the low priority queue pointers at A"),
+    indirect("saveh", 0x3E).stack(1, 0).cycles(4).about("Save the high priority queue pointers at A"),
+    indirect("wcnt", 0x3F).stack(1, 2).cycles(5).about("Split a byte address into words and bytes"),
+    indirect("shr", 0x40).stack(2, 1).cycles(2).about("Logical shift B right by A"),
+    indirect("shl", 0x41).stack(2, 1).cycles(2).about("Logical shift B left by A"),
+    indirect("mint", 0x42).stack(0, 1).about("Load the most negative integer"),
+    indirect("alt", 0x43).cycles(2).about("Start an ALT"),
+    indirect("altwt", 0x44).cycles(5).about("Wait for a guard of an ALT to be ready"),
+    indirect("altend", 0x45).cycles(4).about("Jump to the selected guard of an ALT"),
+    indirect("and", 0x46).stack(2, 1).about("Bitwise and"),
+    indirect("enbt", 0x47).stack(2, 1).cycles(8).about("Enable a timer ALT guard"),
+    indirect("enbc", 0x48).stack(2, 1).cycles(7).about("Enable a channel ALT guard"),
+    indirect("enbs", 0x49).stack(1, 1).cycles(3).about("Enable a skip ALT guard"),
+    indirect("move", 0x4A).stack(3, 0).cycles(8).about("Copy A bytes from C to B"),
+    indirect("or", 0x4B).stack(2, 1).about("Bitwise or"),
+    indirect("csngl", 0x4C).stack(2, 1).cycles(3).about("Set the error flag unless B:A fits in a word"),
+    indirect("ccnt1", 0x4D).stack(2, 1).cycles(3).about("Set the error flag unless 0 < B <= A"),
+    indirect("talt", 0x4E).cycles(4).about("Start a timer ALT"),
+    indirect("ldiff", 0x4F).stack(3, 2).cycles(3).about("B - A - borrow C, leaving the borrow"),
+    indirect("sthb", 0x50).stack(1, 0).about("Store the high priority back pointer"),
+    indirect("taltwt", 0x51).cycles(15).about("Wait for a guard of a timer ALT to be ready"),
+    indirect("sum", 0x52).stack(2, 1).about("B + A without overflow checking"),
+    indirect("mul", 0x53).stack(2, 1).cycles(38).about("B * A with overflow checking"),
+    indirect("sttimer", 0x54).stack(1, 0).about("Start both clocks at A"),
+    indirect("stoperr", 0x55).cycles(2).about("Stop the process if the error flag is set"),
+    indirect("cword", 0x56).stack(2, 1).cycles(5).about("Set the error flag unless B fits in a part word of A"),
+    indirect("clrhalterr", 0x57).about("Clear HaltOnError"),
+    indirect("sethalterr", 0x58).about("Set HaltOnError"),
+    indirect("testhalterr", 0x59).stack(0, 1).cycles(2).about("Load HaltOnError"),
+    indirect("dup", 0x5A).stack(0, 1).on(Extended).about("Duplicate A"),
+    indirect("move2dinit", 0x5B).stack(3, 0).cycles(8).on(BlockAndBitOps).about("Set the strides and row count of 2D moves"),
+    indirect("move2dall", 0x5C).stack(3, 0).cycles(23).on(BlockAndBitOps).about("Copy a 2D block of A by rows from C to B"),
+    indirect("move2dnonzero", 0x5D).stack(3, 0).cycles(23).on(BlockAndBitOps).about("Copy the non zero bytes of a 2D block"),
+    indirect("move2dzero", 0x5E).stack(3, 0).cycles(23).on(BlockAndBitOps).about("Copy the zero bytes of a 2D block"),
+    indirect("unpacksn", 0x63).stack(2, 3).cycles(15).on(SoftFloat).about("Unpack the single length real A"),
+    indirect("postnormsn", 0x6C).stack(3, 3).cycles(5).on(SoftFloat).about("Normalise an unpacked single length result"),
+    indirect("roundsn", 0x6D).stack(3, 1).cycles(12).on(SoftFloat).about("Round and pack a single length result"),
+    indirect("ldinf", 0x71).stack(0, 1).on(SoftFloat).about("Load single length infinity"),
+    indirect("fmul", 0x72).stack(2, 1).cycles(35).on(SoftFloat).about("Fractional multiply"),
+    indirect("cflerr", 0x73).stack(1, 1).cycles(3).on(SoftFloat).about("Set the error flag if A is an infinity or NaN"),
+    indirect("crcword", 0x74).stack(3, 1).cycles(35).on(BlockAndBitOps).about("Add the word C to the CRC B with generator A"),
+    indirect("crcbyte", 0x75).stack(3, 1).cycles(11).on(BlockAndBitOps).about("Add the top byte of C to the CRC B with generator A"),
+    indirect("bitcnt", 0x76).stack(2, 1).cycles(2).on(BlockAndBitOps).about("Count the set bits of A and add B"),
+    indirect("bitrevword", 0x77).stack(1, 1).cycles(36).on(BlockAndBitOps).about("Reverse the bits of A"),
+    indirect("bitrevnbits", 0x78).stack(2, 1).cycles(4).on(BlockAndBitOps).about("Reverse the low A bits of B"),
+    indirect("pop", 0x79).stack(1, 0).on(Extended).about("Discard A"),
+    indirect("timeslice", 0x7B).cycles(3).on(Extended).about("Give way to the next low priority process"),
+    indirect("ldmemstartval", 0x7E).stack(0, 1).on(Extended).about("Load the first free on-chip address"),
+    indirect("wsubdb", 0x81).stack(2, 1).cycles(3).on(Extended).about("Double word subscript, A + 8B"),
+    indirect("fpldnldbi", 0x82).stack(2, 0).cycles(6).on(Fpu).about("Load the double length real B indexed by A"),
+    indirect("fpchkerr", 0x83).on(Fpu).about("Set the error flag if FP_Error is set"),
+    indirect("fpstnldb", 0x84).stack(1, 0).cycles(3).on(Fpu).about("Store FA as a double length real at A"),
+    indirect("fpldnlsni", 0x86).stack(2, 0).cycles(4).on(Fpu).about("Load the single length real B indexed by A"),
+    indirect("fpadd", 0x87).cycles(6).on(Fpu).about("FB + FA"),
+    indirect("fpstnlsn", 0x88).stack(1, 0).cycles(2).on(Fpu).about("Store FA as a single length real at A"),
+    indirect("fpsub", 0x89).cycles(6).on(Fpu).about("FB - FA"),
+    indirect("fpldnldb", 0x8A).stack(1, 0).cycles(3).on(Fpu).about("Load the double length real at A"),
+    indirect("fpmul", 0x8B).cycles(11).on(Fpu).about("FB * FA"),
+    indirect("fpdiv", 0x8C).cycles(16).on(Fpu).about("FB / FA"),
+    indirect("fpldnlsn", 0x8E).stack(1, 0).cycles(2).on(Fpu).about("Load the single length real at A"),
+    indirect("fpremfirst", 0x8F).stack(0, 1).cycles(36).on(Fpu).about("Start the remainder FB rem FA"),
+    indirect("fpremstep", 0x90).stack(0, 1).cycles(32).on(Fpu).about("Continue the remainder, A is true when done"),
+    indirect("fpnan", 0x91).stack(0, 1).cycles(2).on(Fpu).about("Test whether FA is a NaN"),
+    indirect("fpordered", 0x92).stack(0, 1).cycles(3).on(Fpu).about("Test whether neither FA nor FB is a NaN"),
+    indirect("fpnotfinite", 0x93).stack(0, 1).cycles(2).on(Fpu).about("Test whether FA is an infinity or NaN"),
+    indirect("fpgt", 0x94).stack(0, 1).cycles(4).on(Fpu).about("FB > FA"),
+    indirect("fpeq", 0x95).stack(0, 1).cycles(3).on(Fpu).about("FB = FA"),
+    indirect("fpi32tor32", 0x96).stack(1, 0).cycles(8).on(Fpu).about("Load the integer at A as a single length real"),
+    indirect("fpi32tor64", 0x98).stack(1, 0).cycles(8).on(Fpu).about("Load the integer at A as a double length real"),
+    indirect("fpb32tor64", 0x9A).stack(1, 0).cycles(8).on(Fpu).about("Load the unsigned integer at A as a double length real"),
+    indirect("fptesterr", 0x9C).stack(0, 1).cycles(2).on(Fpu).about("Load and clear FP_Error, true when clear"),
+    indirect("fprtoi32", 0x9D).cycles(7).on(Fpu).about("Round FA to an integer valued real in range"),
+    indirect("fpstnli32", 0x9E).stack(1, 0).cycles(4).on(Fpu).about("Store FA as an integer at A"),
+    indirect("fpldzerosn", 0x9F).cycles(2).on(Fpu).about("Load single length zero"),
+    indirect("fpldzerodb", 0xA0).cycles(2).on(Fpu).about("Load double length zero"),
+    indirect("fpint", 0xA1).cycles(5).on(Fpu).about("Round FA to an integer valued real"),
+    indirect("fpdup", 0xA3).on(Fpu).about("Duplicate FA"),
+    indirect("fprev", 0xA4).on(Fpu).about("Swap FA and FB"),
+    indirect("fpldnladddb", 0xA6).stack(1, 0).cycles(10).on(Fpu).about("Add the double length real at A to FA"),
+    indirect("fpldnlmuldb", 0xA8).stack(1, 0).cycles(21).on(Fpu).about("Multiply FA by the double length real at A"),
+    indirect("fpldnladdsn", 0xAA).stack(1, 0).cycles(8).on(Fpu).about("Add the single length real at A to FA"),
+    indirect("fpentry", 0xAB).stack(1, 0).on(Fpu).about("Execute the floating point operation selected by A"),
+    indirect("fpldnlmulsn", 0xAC).stack(1, 0).cycles(13).on(Fpu).about("Multiply FA by the single length real at A"),
+    indirect("lddevid", 0x17C).stack(0, 1).on(Extended).about("Load the device identity")
+];
+
+/// Operations executed through fpentry, in code order
+pub static FPENTRY_INSTRUCTIONS: [InstructionInfo; 19] = [
+    fpentry("fpusqrtfirst", 0x01).cycles(27).on(Fpu).about("Start the square root of FA"),
+    fpentry("fpusqrtstep", 0x02).cycles(42).on(Fpu).about("Refine the square root estimate"),
+    fpentry("fpusqrtlast", 0x03).cycles(8).on(Fpu).about("Finish the square root, correctly rounded"),
+    fpentry("fpurp", 0x04).on(Fpu).about("Round the next operation towards plus infinity"),
+    fpentry("fpurm", 0x05).on(Fpu).about("Round the next operation towards minus infinity"),
+    fpentry("fpurz", 0x06).on(Fpu).about("Round the next operation towards zero"),
+    fpentry("fpur32tor64", 0x07).cycles(3).on(Fpu).about("Convert FA from single to double length"),
+    fpentry("fpur64tor32", 0x08).cycles(3).on(Fpu).about("Convert FA from double to single length"),
+    fpentry("fpuexpdec32", 0x09).cycles(6).on(Fpu).about("Divide FA by 2^32"),
+    fpentry("fpuexpinc32", 0x0A).cycles(6).on(Fpu).about("Multiply FA by 2^32"),
+    fpentry("fpuabs", 0x0B).cycles(2).on(Fpu).about("Absolute value of FA"),
+    fpentry("fpunoround", 0x0D).cycles(2).on(Fpu).about("Convert FA to single length without rounding"),
+    fpentry("fpuchki32", 0x0E).cycles(3).on(Fpu).about("Set FP_Error unless FA is in the 32 bit integer range"),
+    fpentry("fpuchki64", 0x0F).cycles(3).on(Fpu).about("Set FP_Error unless FA is in the 64 bit integer range"),
+    fpentry("fpudivby2", 0x11).cycles(6).on(Fpu).about("Divide FA by 2"),
+    fpentry("fpumulby2", 0x12).cycles(6).on(Fpu).about("Multiply FA by 2"),
+    fpentry("fpurn", 0x22).on(Fpu).about("Round the next operation to nearest"),
+    fpentry("fpuseterr", 0x23).on(Fpu).about("Set FP_Error"),
+    fpentry("fpuclrerr", 0x9C).on(Fpu).about("Clear FP_Error")
+];
+
+/// Find an instruction by mnemonic
+pub fn lookup(mnemonic: &str) -> Option<&'static InstructionInfo>{
+    DIRECT_INSTRUCTIONS.iter()
+        .chain(INDIRECT_INSTRUCTIONS.iter())
+        .chain(FPENTRY_INSTRUCTIONS.iter())
+        .find(|info| info.mnemonic == mnemonic)
+}
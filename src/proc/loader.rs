@@ -0,0 +1,57 @@
+/* Place programs in memory and prepare the processor to run them */
+
+use std::fs;
+use std::io;
+use std::ops::Range;
+use std::path::Path;
+
+use super::{ATYPE, Proc};
+use crate::mem::MemErr;
+
+/// Where a program is placed and how execution starts
+#[derive(Debug, Clone, PartialEq)]
+pub struct LoadConfig{
+    /// Address of the first byte of the image
+    pub address: ATYPE,
+    /// Initial Iptr
+    pub entry: ATYPE,
+    /// Initial Wptr
+    pub workspace: ATYPE
+}
+
+impl LoadConfig{
+    /// Load at address and start executing from the first byte
+    pub fn new(address: ATYPE, workspace: ATYPE) -> Self{
+        Self{
+            address,
+            entry: address,
+            workspace
+        }
+    }
+
+    /// Start executing from entry instead of the load address
+    pub fn with_entry(mut self, entry: ATYPE) -> Self{
+        self.entry = entry;
+        self
+    }
+}
+
+impl Proc{
+    /// Copy an image (raw or assembled) into memory and set Iptr and Wptr
+    /// Returns the range of addresses written, its end is where Iptr goes
+    /// after the last byte, MostNeg for an image that ends at MostPos
+    pub fn load_image(&mut self, image: &[u8], config: &LoadConfig) -> Result<Range<ATYPE>, MemErr>{
+        self.mem.write_bytes(config.address, image)?;
+        self.pc = config.entry;
+        self.operand = 0;
+        self.update_wdesc(config.workspace | (self.descriptor & 0b1));
+        Ok(config.address..config.address.wrapping_add(image.len() as ATYPE))
+    }
+
+    /// Load a raw binary file
+    pub fn load_file<P: AsRef<Path>>(&mut self, path: P, config: &LoadConfig) -> io::Result<Range<ATYPE>>{
+        let image = fs::read(path)?;
+        self.load_image(&image, config)
+            .map_err(|e| io::Error::new(io::ErrorKind::InvalidInput, format!("{:?}", e)))
+    }
+}
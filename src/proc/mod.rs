@@ -1,14 +1,31 @@
 mod secondary;
+mod loader;
+mod timer;
+mod fpu;
+mod rounding;
+mod model;
+mod info;
 
 use std::collections::{hash_map::IntoKeys, HashMap};
 
-use secondary::{define_w_prefix, define_wo_prefix};
+use secondary::{define_block_and_bit_ops, define_extended, define_soft_float, define_w_prefix, define_wo_prefix};
+use fpu::{define_fpu, FloatStack};
+use rounding::RoundMode;
 
+use std::fmt;
 use std::rc::Rc;
 
 use crate::mem::*;
 
+pub use loader::LoadConfig;
+pub use timer::TimerMode;
+pub use model::{Model, ModelDescriptor, ModelErr};
+pub use info::{Encoding, InstructionInfo};
+use timer::Timers;
+
+#[allow(clippy::upper_case_acronyms)]
 type RTYPE = i32;
+#[allow(clippy::upper_case_acronyms)]
 type ATYPE = i32;
 
 // bits
@@ -18,6 +35,31 @@ const ERROR_FLAG: usize = 0x8000_0000;
 
 const NOT_PROCESS_P: i32 = 0x8000_0000u32 as i32;
 
+// Workspace slots below Wptr used while a process is not running
+const IPTR_S: i32 = -4;
+const LINK_S: i32 = -8;
+const POINTER_S: i32 = -12;
+const STATE_S: i32 = -12;
+const TLINK_S: i32 = -16;
+const TIME_S: i32 = -20;
+
+// ALT states held in State.s
+const ENABLING_P: i32 = NOT_PROCESS_P + 1;
+const WAITING_P: i32 = NOT_PROCESS_P + 2;
+const READY_P: i32 = NOT_PROCESS_P + 3;
+
+// Timer ALT states held in TLink.s
+const TIME_SET_P: i32 = NOT_PROCESS_P + 1;
+const TIME_NOT_SET_P: i32 = NOT_PROCESS_P + 2;
+
+// Wptr[0] before any ALT guard has been selected
+const NONE_SELECTED_O: i32 = -1;
+
+/// Time t1 is later than t2, allowing for the clock wrapping around
+fn is_after(t1: RTYPE, t2: RTYPE) -> bool{
+    t1.wrapping_sub(t2) > 0
+}
+
 enum OpVal{
     Int(RTYPE),
     List(Vec<RTYPE>),
@@ -46,17 +88,117 @@ pub enum DirectOp{
     OPR
 }
 
-enum IndirectOp{
-    None // TODO
+impl DirectOp{
+    /// Decode the function code in the top nibble of an instruction byte
+    pub fn from_instruction(instruction: u8) -> Self{
+        match instruction >> 4{
+            0x0 => DirectOp::JUMP,
+            0x1 => DirectOp::LDLP,
+            0x2 => DirectOp::PFIX,
+            0x3 => DirectOp::LDNL,
+            0x4 => DirectOp::LDC,
+            0x5 => DirectOp::LDNLP,
+            0x6 => DirectOp::NFIX,
+            0x7 => DirectOp::LDL,
+            0x8 => DirectOp::ADC,
+            0x9 => DirectOp::CALL,
+            0xA => DirectOp::CJ,
+            0xB => DirectOp::AJW,
+            0xC => DirectOp::EQC,
+            0xD => DirectOp::STL,
+            0xE => DirectOp::STNL,
+            _ => DirectOp::OPR
+        }
+    }
+    
+    /// Metadata of the instruction
+    pub fn info(&self) -> &'static InstructionInfo{
+        &info::DIRECT_INSTRUCTIONS[self.clone() as usize]
+    }
 }
 
-#[derive(Debug)]
+/// Cause of an instruction failing
+#[derive(Debug, Clone, PartialEq)]
 pub enum OpErr{
     Err,
     Overflow,
     DivideByZero,
     NotSingle,
-    Count
+    Count,
+    /// Operation does not exist
+    IllegalOpcode,
+    /// Word access to an address that is not a multiple of 4
+    Unaligned(ATYPE),
+    /// Access outside of memory
+    OutOfRange(ATYPE),
+    /// Operation exists but the emulator does not support it yet
+    Unimplemented,
+    /// FP_Error was set when checked by fpchkerr
+    FloatingPoint
+}
+
+impl OpErr{
+    /// Traps stop the instruction, other errors only set the error flag
+    pub fn is_trap(&self) -> bool{
+        matches!(self, OpErr::IllegalOpcode | OpErr::Unaligned(_) | OpErr::OutOfRange(_) | OpErr::Unimplemented)
+    }
+}
+
+impl From<MemErr> for OpErr{
+    fn from(e: MemErr) -> Self {
+        match e{
+            MemErr::Unaligned(address) => OpErr::Unaligned(address),
+            MemErr::OutOfRange(address) => OpErr::OutOfRange(address)
+        }
+    }
+}
+
+/// Decoded instruction
+#[derive(Debug, Clone, PartialEq)]
+pub enum Mnemonic{
+    Direct(DirectOp),
+    /// Operation name and code executed through opr
+    Indirect(String, usize),
+    /// Operand of opr that is not an operation
+    Illegal(RTYPE),
+    /// Instruction byte could not be fetched
+    Unfetched
+}
+
+/// Failure while executing an instruction
+#[derive(Debug, Clone, PartialEq)]
+pub struct ExecError{
+    /// Address of the instruction byte that failed
+    pub iptr: ATYPE,
+    /// Raw instruction byte
+    pub byte: u8,
+    pub op: Mnemonic,
+    pub cause: OpErr
+}
+
+impl fmt::Display for ExecError{
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let name = match &self.op{
+            Mnemonic::Direct(op) => op.info().mnemonic.to_string(),
+            Mnemonic::Indirect(name, _) => name.clone(),
+            Mnemonic::Illegal(code) => format!("opr {:#x}", code),
+            Mnemonic::Unfetched => "fetch".to_string()
+        };
+        write!(f, "{:?} at {:#x} ({:#04x} {})", self.cause, self.iptr, self.byte, name)
+    }
+}
+
+/// What to do when an illegal or unimplemented operation is executed
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum IllegalOpPolicy{
+    /// Return an error, the processor can keep stepping
+    Trap,
+    /// Return an error and halt the processor
+    Halt,
+    /// Set the error flag and continue
+    SetError,
+    /// Treat as a no-op
+    Skip
 }
 
 type OperandType = u8;
@@ -70,7 +212,7 @@ pub enum Flag{
     ERROR
 }
 
-#[derive(PartialEq)]
+#[derive(PartialEq, Clone, Copy, Debug)]
 #[repr(u8)]
 enum Priority{
     Low = 1,
@@ -81,7 +223,9 @@ struct ProcLibrary{
     pub direct: [OpFn; 16],
     indirect_id: HashMap<usize, usize>,
     indirect_fn: Vec<IndirectOpFn>,
-    indirect_name: Vec<String>
+    indirect_name: Vec<String>,
+    indirect_info: Vec<&'static InstructionInfo>,
+    fpentry_id: HashMap<usize, usize>
 }
 
 fn direct() -> [OpFn; 16]{
@@ -89,16 +233,14 @@ fn direct() -> [OpFn; 16]{
         // Jump
         Rc::new(|p, v|{
             let operand = p.shift_operand(v);
-            p.pc = p.pc + operand;
-            if p.priority() == Priority::Low{
-                p.deschedule();
-            }
+            p.pc = p.pc.wrapping_add(operand);
+            p.timeslice()?;
             Ok(OpVal::Null)
         }),
         // LDLP
         Rc::new(|p, v|{
             let operand = p.shift_operand(v);
-            Ok(OpVal::Int(p.workspace + (operand << 2)))
+            Ok(OpVal::Int(p.workspace.wrapping_add(operand << 2)))
         }),
         // PFIX
         Rc::new(|p, v|{
@@ -109,7 +251,7 @@ fn direct() -> [OpFn; 16]{
         Rc::new(|p, v|{
             let operand = p.shift_operand(v) << 2;
             let a = p.stack.pop();
-            Ok(OpVal::Int(p.mem.read(a.wrapping_add(operand))))
+            Ok(OpVal::Int(p.mem.read(a.wrapping_add(operand))?))
         }),
         // LDC
         Rc::new(|p, v|{
@@ -131,40 +273,50 @@ fn direct() -> [OpFn; 16]{
         // LDL
         Rc::new(|p, v|{
            let address = p.workspace.wrapping_add(p.shift_operand(v) << 2);
-           Ok(OpVal::Int(p.mem.read(address)))
+           Ok(OpVal::Int(p.mem.read(address)?))
         }),
         // ADC
         Rc::new(|p, v|{
             let a = p.stack.pop();
             let operand = p.shift_operand(v);
-            Ok(OpVal::Int(a.wrapping_add(operand)))
+            p.stack.push(a.wrapping_add(operand));
+            if a.checked_add(operand).is_none(){
+                p.throw_error(OpErr::Overflow);
+            }
+            Ok(OpVal::Null)
         }),
         // CALL
         Rc::new(|p, v|{
-            // Store register stack
+            // Store register stack and return address below the workspace
             let a = p.stack.pop();
             let b = p.stack.pop();
             let c = p.stack.pop();
-            p.mem.write(p.workspace - 4, a);
-            p.mem.write(p.workspace - 8, b);
-            p.mem.write(p.workspace - 12, c);
-            p.mem.write(p.workspace - 16, p.pc);
-            p.workspace -= 16;
-            p.pc += p.shift_operand(v) - 4;
-            Ok(OpVal::Null)
+            p.mem.write(p.workspace.wrapping_sub(4), c)?;
+            p.mem.write(p.workspace.wrapping_sub(8), b)?;
+            p.mem.write(p.workspace.wrapping_sub(12), a)?;
+            p.mem.write(p.workspace.wrapping_sub(16), p.pc)?;
+            p.update_wdesc((p.workspace.wrapping_sub(16)) | (p.descriptor & 0b1));
+            // Return address is left in A
+            let ret = p.pc;
+            p.pc = p.pc.wrapping_add(p.shift_operand(v));
+            Ok(OpVal::Int(ret))
         }),
         // CJ
         Rc::new(|p, v|{
-           let a = p.stack.pop();
-           if a == 0{
-               p.pc = p.pc.wrapping_add(p.shift_operand(v));
+           let operand = p.shift_operand(v);
+           // Only consumes A when the jump is not taken
+           if p.stack.a() == 0{
+               p.pc = p.pc.wrapping_add(operand);
+           }
+           else{
+               p.stack.pop();
            }
            Ok(OpVal::Null)
         }),
         // AJW
         Rc::new(|p, v|{
             let operand = p.shift_operand(v) << 2;
-            p.update_wdesc((p.workspace + operand) | (p.descriptor & 0b1));
+            p.update_wdesc((p.workspace.wrapping_add(operand)) | (p.descriptor & 0b1));
             
             Ok(OpVal::Null)
         }),
@@ -183,23 +335,25 @@ fn direct() -> [OpFn; 16]{
         Rc::new(|p, v|{
             let offset = p.shift_operand(v) << 2;
             let a = p.stack.pop();
-            p.mem.write(p.workspace + offset, a);
+            p.mem.write(p.workspace.wrapping_add(offset), a)?;
             Ok(OpVal::Null)
         }),
         // STNL
         Rc::new(|p, v|{
-            let b = p.stack.b();
             let a = p.stack.pop();
+            let b = p.stack.pop();
             
             let offset = p.shift_operand(v) << 2;
-            p.mem.write(a+offset, b);
-            Ok(OpVal::Int(a+offset))
+            p.mem.write(a.wrapping_add(offset), b)?;
+            Ok(OpVal::Null)
         }),
         // OPR
         Rc::new(|p, v|{
             let operand = p.shift_operand(v) as usize;
-            let name = p.library.get_indirect_name(operand);
-            p.library.get_indirect(operand).clone()(p)
+            match p.library.get_indirect(operand){
+                Some(f) => f.clone()(p),
+                None => Err(OpErr::IllegalOpcode)
+            }
         })
     ]
 }
@@ -210,21 +364,76 @@ impl ProcLibrary{
             direct: direct(),
             indirect_id: HashMap::new(),
             indirect_fn: Vec::new(),
-            indirect_name: Vec::new()
+            indirect_name: Vec::new(),
+            indirect_info: Vec::new(),
+            fpentry_id: HashMap::new()
+        }
+    }
+    
+    /// Panics when the name is already used, every op needs its own mnemonic
+    fn check_name(&self, name: &str){
+        if self.indirect_name.iter().any(|n| n == name){
+            panic!("Instruction {} defined twice", name);
+        }
+    }
+    
+    /// Table entry of an operation, which gives its code
+    /// Panics if the table doesn't list it with this encoding
+    fn table_entry(name: &str, encoding: Encoding) -> &'static InstructionInfo{
+        match info::lookup(name){
+            Some(entry) if entry.encoding == encoding => entry,
+            Some(entry) => panic!("{} is {:?} {:#x} in the instruction table, not {:?}", name, entry.encoding, entry.code, encoding),
+            None => panic!("{} is not in the instruction table", name)
         }
     }
     
-    /// Define new indirect function
-    fn define_indirect<T: for<'a> Fn(&'a mut Proc) -> OpResult + 'static>(&mut self, name: &str, opcode: usize, f: T){
+    fn push_op<T: for<'a> Fn(&'a mut Proc) -> OpResult + 'static>(&mut self, name: &str, entry: &'static InstructionInfo, f: T) -> usize{
         let id = self.indirect_fn.len();
-        self.indirect_id.insert(opcode, id);
         self.indirect_fn.push(Rc::new(f));
         self.indirect_name.push(name.to_string());
+        self.indirect_info.push(entry);
+        id
+    }
+    
+    /// Define new indirect function, at the opcode the instruction table gives it
+    /// Panics if the opcode or name is already defined
+    fn define_indirect<T: for<'a> Fn(&'a mut Proc) -> OpResult + 'static>(&mut self, name: &str, f: T){
+        self.check_name(name);
+        let entry = Self::table_entry(name, Encoding::Indirect);
+        if let Some(other) = self.get_indirect_name(entry.code){
+            panic!("Opcode {:#x} of {} is already used by {}", entry.code, name, other);
+        }
+        let id = self.push_op(name, entry, f);
+        self.indirect_id.insert(entry.code, id);
+    }
+    
+    /// Define a floating point operation selected by A in fpentry
+    /// Panics if the code or name is already defined
+    fn define_fpentry<T: for<'a> Fn(&'a mut Proc) -> OpResult + 'static>(&mut self, name: &str, f: T){
+        self.check_name(name);
+        let entry = Self::table_entry(name, Encoding::FpEntry);
+        if let Some(id) = self.fpentry_id.get(&entry.code){
+            panic!("fpentry code {:#x} of {} is already used by {}", entry.code, name, self.indirect_name[*id]);
+        }
+        let id = self.push_op(name, entry, f);
+        self.fpentry_id.insert(entry.code, id);
+    }
+    
+    /// Remove the operations model doesn't have, so they are illegal
+    fn remove_unavailable(&mut self, model: &ModelDescriptor){
+        let info = &self.indirect_info;
+        self.indirect_id.retain(|_, id| info[*id].availability.on(model));
+        self.fpentry_id.retain(|_, id| info[*id].availability.on(model));
+    }
+    
+    /// Get function pointer of fpentry operation
+    fn get_fpentry(&self, code: usize) -> Option<&IndirectOpFn>{
+        self.fpentry_id.get(&code).map(|id| &self.indirect_fn[*id])
     }
     
     /// Get function pointer of indirect function
-    fn get_indirect(&self, opcode: usize) -> &IndirectOpFn{
-        &self.indirect_fn[self.indirect_id[&opcode]]
+    fn get_indirect(&self, opcode: usize) -> Option<&IndirectOpFn>{
+        self.indirect_id.get(&opcode).map(|id| &self.indirect_fn[*id])
     }
     
     /// Get all indirect codes
@@ -233,13 +442,48 @@ impl ProcLibrary{
     }
     
     /// Get name of indirect operation
-    fn get_indirect_name(&self, opcode: usize) -> String{
-        self.indirect_name[self.indirect_id[&opcode]].clone()
+    fn get_indirect_name(&self, opcode: usize) -> Option<String>{
+        self.indirect_id.get(&opcode).map(|id| self.indirect_name[*id].clone())
+    }
+    
+    /// Get names and codes of fpentry operations
+    fn get_fpentry_names(&self) -> Vec<(String, usize)>{
+        self.fpentry_id.iter().map(|(code, id)| (self.indirect_name[*id].clone(), *code)).collect()
+    }
+    
+    /// Get metadata of indirect operation
+    fn get_indirect_info(&self, opcode: usize) -> Option<&'static InstructionInfo>{
+        self.indirect_id.get(&opcode).map(|id| self.indirect_info[*id])
+    }
+    
+    /// Get metadata of fpentry operation
+    fn get_fpentry_info(&self, code: usize) -> Option<&'static InstructionInfo>{
+        self.fpentry_id.get(&code).map(|id| self.indirect_info[*id])
     }
 }
 
+/// Set by move2dinit for the following 2D block moves
+#[derive(Debug, Clone, Copy, Default)]
+struct Move2d{
+    source_stride: RTYPE,
+    dest_stride: RTYPE,
+    /// Number of rows
+    length: RTYPE
+}
+
+/// Registers of an interrupted low priority process that have no word
+/// at REGISTER_CACHE
+struct SavedRegisters{
+    operand: RTYPE,
+    fstack: FloatStack,
+    fp_error: bool
+}
+
 pub struct Proc{
     stack: Stack,
+    fstack: FloatStack,
+    fp_error: bool,
+    round_mode: RoundMode,
     
     // Main registers
     pc: ATYPE,
@@ -248,8 +492,24 @@ pub struct Proc{
     
     descriptor: RTYPE,
     
+    // Process queues, indexed by priority
+    fptr: [ATYPE; 2],
+    bptr: [ATYPE; 2],
+    
+    timers: Timers,
+    
+    // Low priority process interrupted by high priority, the rest of
+    // its registers are at REGISTER_CACHE
+    interrupted: SavedRegisters,
+    
+    move2d: Move2d,
+    
+    model: ModelDescriptor,
+    
     status: usize,
-    error: usize,
+    halted: bool,
+    halt_cause: Option<OpErr>,
+    illegal_policy: IllegalOpPolicy,
     
     // Additional Register
     
@@ -261,35 +521,114 @@ pub struct Proc{
 }
 
 impl Proc{
+    /// A T800
     pub fn new(workspace: ATYPE) -> Self{
+        Self::with_model(workspace, ModelDescriptor::default()).expect("The T800 descriptor is valid")
+    }
+    
+    /// A processor with the instructions, on-chip RAM and clock of model
+    pub fn with_model(workspace: ATYPE, model: ModelDescriptor) -> Result<Self, ModelErr>{
+        model.validate()?;
+        let library = ProcLibrary::new();
+        let mem = Mem::new(model.mem_map());
         let mut p = Proc {
             stack: Stack::new(),
+            fstack: FloatStack::new(),
+            fp_error: false,
+            round_mode: RoundMode::Nearest,
             pc: ATYPE::default(),
-            workspace: workspace,
+            workspace: workspace & !0b11,
             status: 0,
-            error: 0,
-            descriptor: 0,
+            halted: false,
+            halt_cause: None,
+            illegal_policy: IllegalOpPolicy::Trap,
+            descriptor: workspace,
+            fptr: [NOT_PROCESS_P; 2],
+            bptr: [NOT_PROCESS_P; 2],
+            timers: Timers::new(model.clock_mhz),
+            interrupted: SavedRegisters{
+                operand: 0,
+                fstack: FloatStack::new(),
+                fp_error: false
+            },
+            move2d: Move2d::default(),
+            model,
             operand: RTYPE::default(),
-            mem: Mem::new(DRAM_SIZE),
-            library: ProcLibrary::new()
+            mem,
+            library
         };
         p.setup();
-        p
+        p.clear_reserved_words().expect("MemStart is after the reserved words");
+        Ok(p)
+    }
+    
+    /// Empty the link and event channels, timer queues and interrupted
+    /// registers, as at reset
+    fn clear_reserved_words(&mut self) -> Result<(), MemErr>{
+        for link in 0..4{
+            self.mem.write(LINK_OUTPUT + 4 * link, NOT_PROCESS_P)?;
+            self.mem.write(LINK_INPUT + 4 * link, NOT_PROCESS_P)?;
+        }
+        for word in [EVENT_CHANNEL, TPTR_LOC, TPTR_LOC + 4, REGISTER_CACHE]{
+            self.mem.write(word, NOT_PROCESS_P)?;
+        }
+        Ok(())
     }
     
     /// Throw error flag in processor
+    /// Halts the processor if HaltOnError is set
     pub fn throw_error(&mut self, e: OpErr){
-        todo!("Error flag not implemented");
+        self.status |= ERROR_FLAG;
+        if self.halt_on_error() && !self.halted{
+            self.halted = true;
+            self.halt_cause = Some(e);
+        }
+    }
+    
+    /// Check the error flag
+    pub fn error_flag(&self) -> bool{
+        self.status & ERROR_FLAG != 0
+    }
+    
+    /// Clear the error flag
+    pub fn clear_error(&mut self){
+        self.status &= !ERROR_FLAG;
+    }
+    
+    /// Check the HaltOnError flag
+    pub fn halt_on_error(&self) -> bool{
+        self.status & HALT_ON_ERROR_BIT != 0
+    }
+    
+    /// Set or clear the HaltOnError flag
+    pub fn set_halt_on_error(&mut self, halt: bool){
+        if halt{
+            self.status |= HALT_ON_ERROR_BIT;
+        }
+        else{
+            self.status &= !HALT_ON_ERROR_BIT;
+        }
+    }
+    
+    /// Processor has stopped after an error with HaltOnError set
+    /// or after an illegal operation with the Halt policy
+    pub fn is_halted(&self) -> bool{
+        self.halted
+    }
+    
+    /// Set how illegal and unimplemented operations are handled
+    pub fn set_illegal_policy(&mut self, policy: IllegalOpPolicy){
+        self.illegal_policy = policy;
     }
     
     /// Get program counter
     pub fn program_counter(&self) -> i32{
-        return self.pc;
+        self.pc
     }
     
     /// Get active workspace pointer
     pub fn workspace_pointer(&self) -> i32{
-        return self.workspace
+        self.workspace
     }
     
     /// Get a register from the stack
@@ -301,69 +640,208 @@ impl Proc{
     pub fn get_indirect_ops(&self) -> Vec<(String, usize)>{
         let mut maps = Vec::new();
         for op in self.library.get_indirect_codes(){
-            maps.push( (self.library.get_indirect_name(op), op) );
+            if let Some(name) = self.library.get_indirect_name(op){
+                maps.push( (name, op) );
+            }
         }
         maps
     }
     
+    /// Get names of operations reached through fpentry
+    pub fn get_fpentry_ops(&self) -> Vec<(String, usize)>{
+        self.library.get_fpentry_names()
+    }
+    
+    /// Metadata of every instruction this processor has, direct instructions
+    /// first, then operations by opcode and fpentry operations by code
+    pub fn instruction_set(&self) -> Vec<&'static InstructionInfo>{
+        let mut set: Vec<&'static InstructionInfo> = info::DIRECT_INSTRUCTIONS.iter().collect();
+        let mut codes: Vec<usize> = self.library.get_indirect_codes().collect();
+        codes.sort();
+        set.extend(codes.into_iter().filter_map(|code| self.library.get_indirect_info(code)));
+        let mut codes: Vec<usize> = self.library.fpentry_id.keys().copied().collect();
+        codes.sort();
+        set.extend(codes.into_iter().filter_map(|code| self.library.get_fpentry_info(code)));
+        set
+    }
+    
+    /// Metadata of the operation opr runs for opcode, if it exists
+    pub fn indirect_info(&self, opcode: usize) -> Option<&'static InstructionInfo>{
+        self.library.get_indirect_info(opcode)
+    }
+    
+    /// Metadata of the operation fpentry runs for code, if it exists
+    pub fn fpentry_info(&self, code: usize) -> Option<&'static InstructionInfo>{
+        self.library.get_fpentry_info(code)
+    }
+    
+    /// Install the instructions, leaving out those the model doesn't have
+    /// so they are illegal
     fn setup(&mut self){
         define_wo_prefix(&mut self.library);
         define_w_prefix(&mut self.library);
+        define_extended(&mut self.library);
+        define_block_and_bit_ops(&mut self.library);
+        define_soft_float(&mut self.library);
+        define_fpu(&mut self.library);
+        self.library.remove_unavailable(&self.model);
+    }
+    
+    /// The model the processor was built as
+    pub fn model(&self) -> &ModelDescriptor{
+        &self.model
     }
     
     /// Get the pointer at the front of the process queue
     fn get_front_pointer(&self, pri: Priority) -> RTYPE{
-        match pri{
-            Priority::Low => self.mem.read(FRONT_PTR_1),
-            Priority::High => self.mem.read(FRONT_PTR_0)
-        }
+        self.fptr[pri as usize]
     }
     
     /// Set the pointer at the start of the process queue
     fn set_front_pointer(&mut self, pri: Priority, v: RTYPE){
-        match pri{
-            Priority::Low => self.mem.write(FRONT_PTR_1, v),
-            Priority::High => self.mem.write(FRONT_PTR_0, v)
-        }
+        self.fptr[pri as usize] = v;
     }
     
     /// Get the pointer at the back of the process queue
     fn get_back_pointer(&self, pri: Priority) -> RTYPE{
-        match pri{
-            Priority::Low => self.mem.read(BACK_PTR_1),
-            Priority::High => self.mem.read(BACK_PTR_0)
-        }
+        self.bptr[pri as usize]
     }
     
     /// Set the pointer at the back of the process queue
     fn set_back_pointer(&mut self, pri: Priority, v: RTYPE){
-        match pri{
-            Priority::Low => self.mem.write(BACK_PTR_1, v),
-            Priority::High => self.mem.write(BACK_PTR_0, v)
-        }
+        self.bptr[pri as usize] = v;
     }
     
-    /// Get the value of the clock register
-    fn get_clock_register(&self, _pri:Priority) -> RTYPE{
-        0 // STUB
+    /// Descheduling point for j and lend
+    /// A low priority process that has used up its timeslice
+    /// gives way to the next low priority process
+    fn timeslice(&mut self) -> Result<(), OpErr>{
+        if self.priority() == Priority::Low
+            && self.timeslice_expired()
+            && self.get_front_pointer(Priority::Low) != NOT_PROCESS_P{
+            self.deschedule()?;
+        }
+        Ok(())
     }
     
-    /// Check if the current process should be switched
-    /// If so, adds itself to the back of the process queue
+    /// Add the current process to the back of the low priority queue
     /// and set status flag
-    fn deschedule(&mut self){
+    fn deschedule(&mut self) -> Result<(), OpErr>{
         // We save data at a few locations
-        self.mem.write(self.workspace - 4, self.pc);
-        if self.get_front_pointer(Priority::Low) == NOT_PROCESS_P{
-            self.set_front_pointer(Priority::Low, self.workspace);
+        self.mem.write(self.workspace.wrapping_add(IPTR_S), self.pc)?;
+        self.enqueue(Priority::Low, self.workspace)?;
+        self.status |= GO_TO_SNP_BIT;
+        Ok(())
+    }
+    
+    /// Add a process to the back of a process queue, linked through Link.s
+    fn enqueue(&mut self, pri: Priority, waddress: ATYPE) -> Result<(), OpErr>{
+        if self.get_front_pointer(pri) == NOT_PROCESS_P{
+            self.set_front_pointer(pri, waddress);
+        }
+        else{
+            let bp = self.get_back_pointer(pri);
+            self.mem.write(bp.wrapping_add(LINK_S), waddress)?;
+        }
+        self.set_back_pointer(pri, waddress);
+        Ok(())
+    }
+    
+    /// Remove the process at the front of a process queue
+    fn dequeue(&mut self, pri: Priority) -> Result<Option<ATYPE>, OpErr>{
+        let front = self.get_front_pointer(pri);
+        if front == NOT_PROCESS_P{
+            return Ok(None)
+        }
+        if front == self.get_back_pointer(pri){
+            self.set_front_pointer(pri, NOT_PROCESS_P);
+        }
+        else{
+            let next = self.mem.read(front.wrapping_add(LINK_S))?;
+            self.set_front_pointer(pri, next);
+        }
+        Ok(Some(front))
+    }
+    
+    /// Start the next process, high priority first, then any interrupted
+    /// low priority process. The processor is idle if both queues are empty
+    fn start_next_process(&mut self) -> Result<(), OpErr>{
+        self.status &= !GO_TO_SNP_BIT;
+        for pri in [Priority::High, Priority::Low]{
+            if pri == Priority::Low && self.mem.read(REGISTER_CACHE)? != NOT_PROCESS_P{
+                return self.restore_registers();
+            }
+            if let Some(waddress) = self.dequeue(pri)?{
+                self.update_wdesc(waddress | pri as RTYPE);
+                self.start_timeslice();
+                return self.activate_process();
+            }
+        }
+        self.update_wdesc(NOT_PROCESS_P);
+        Ok(())
+    }
+    
+    /// No process is running, waiting for one to be scheduled
+    pub fn is_idle(&self) -> bool{
+        self.descriptor == NOT_PROCESS_P
+    }
+    
+    /// Idle with no timers that could wake a process
+    pub fn is_stalled(&self) -> bool{
+        self.is_idle() && !self.timers_pending()
+    }
+    
+    /// Save Iptr and stop running the current process
+    /// It is not queued, another process must reschedule it
+    fn wait(&mut self) -> Result<(), OpErr>{
+        self.mem.write(self.workspace.wrapping_add(IPTR_S), self.pc)?;
+        self.status |= GO_TO_SNP_BIT;
+        Ok(())
+    }
+    
+    /// Transfer count bytes at pointer over the channel word at channel
+    /// The first process to arrive waits on the channel, the second
+    /// copies the message and reschedules its partner
+    fn communicate(&mut self, channel: ATYPE, pointer: ATYPE, count: RTYPE, input: bool) -> Result<(), OpErr>{
+        let partner = self.mem.read(channel)?;
+        if partner == NOT_PROCESS_P{
+            self.mem.write(channel, self.descriptor)?;
+            self.mem.write(self.workspace.wrapping_add(POINTER_S), pointer)?;
+            return self.wait();
+        }
+        
+        let partner_pointer = self.mem.read((partner & !0b11).wrapping_add(POINTER_S))?;
+        if !input && (partner_pointer == ENABLING_P || partner_pointer == WAITING_P || partner_pointer == READY_P){
+            // Partner is an ALT, wait on the channel and make its guard ready
+            self.mem.write(channel, self.descriptor)?;
+            self.mem.write(self.workspace.wrapping_add(POINTER_S), pointer)?;
+            if partner_pointer != READY_P{
+                self.mem.write((partner & !0b11).wrapping_add(STATE_S), READY_P)?;
+            }
+            if partner_pointer == WAITING_P{
+                self.run_process(partner)?;
+            }
+            return self.wait();
+        }
+        
+        if input{
+            self.mem.copy(partner_pointer, pointer, count as u32)?;
         }
         else{
-            // Update the last value in queue
-            self.mem.write(self.get_back_pointer(Priority::Low) - 8, self.workspace);
+            self.mem.copy(pointer, partner_pointer, count as u32)?;
+        }
+        self.mem.write(channel, NOT_PROCESS_P)?;
+        self.run_process(partner)
+    }
+    
+    /// Select an ALT guard if none has been selected yet
+    /// Wptr[0] holds the offset to the selected guard's code
+    fn select_guard(&mut self, offset: RTYPE) -> Result<bool, OpErr>{
+        if self.mem.read(self.workspace)? != NONE_SELECTED_O{
+            return Ok(false)
         }
-        self.set_back_pointer(Priority::Low, self.workspace);
-        self.status = self.status | GO_TO_SNP_BIT;
-        //self.mem.write(self.workspace - 8, );
+        self.mem.write(self.workspace, offset)?;
+        Ok(true)
     }
     
     fn priority(&self) -> Priority{
@@ -385,33 +863,51 @@ impl Proc{
         o
     }
     
-    /// Save registers to memory
-    fn save_registers(&mut self){
-        // Save registers space
-        self.mem.write(REGISTER_CACHE, self.descriptor);
-        if self.descriptor != NOT_PROCESS_P + 1{
-            self.mem.write(REGISTER_CACHE+4, self.pc);
-            self.mem.write(REGISTER_CACHE+8, self.stack.a());
-            self.mem.write(REGISTER_CACHE+12, self.stack.b());
-            self.mem.write(REGISTER_CACHE+16, self.stack.c());
-            self.mem.write(REGISTER_CACHE+20, self.status as i32);
-            // TODO: Cache float stack
+    /// Save the low priority registers when a high priority process interrupts
+    fn save_registers(&mut self) -> Result<(), OpErr>{
+        let words = [self.descriptor, self.pc, self.stack.a(), self.stack.b(), self.stack.c(), self.status as RTYPE];
+        for (i, v) in words.iter().enumerate(){
+            self.mem.write(REGISTER_CACHE + 4 * i as RTYPE, *v)?;
         }
+        self.interrupted = SavedRegisters{
+            operand: self.operand,
+            fstack: self.fstack,
+            fp_error: self.fp_error
+        };
+        Ok(())
     }
     
-    /// Load registers from memory
-    fn restore_registers(&mut self){
-        let wdesc = self.mem.read(REGISTER_CACHE);
+    /// Resume the interrupted low priority process
+    /// The error flags are shared and keep their current value
+    fn restore_registers(&mut self) -> Result<(), OpErr>{
+        let mut words = [0; 6];
+        for (i, v) in words.iter_mut().enumerate(){
+            *v = self.mem.read(REGISTER_CACHE + 4 * i as RTYPE)?;
+        }
+        self.mem.write(REGISTER_CACHE, NOT_PROCESS_P)?;
+        let [wdesc, pc, a, b, c, status] = words;
         self.update_wdesc(wdesc);
-        if self.descriptor != NOT_PROCESS_P + 1{
-            self.pc = self.mem.read(REGISTER_CACHE+4);
-            self.stack.set(0, self.mem.read(REGISTER_CACHE+8));
-            self.stack.set(1, self.mem.read(REGISTER_CACHE+12));
-            self.stack.set(2, self.mem.read(REGISTER_CACHE+16));
-            self.status = self.mem.read(REGISTER_CACHE+20) as usize;
-            
-            // TODO: Restore float stack
+        self.pc = pc;
+        self.operand = self.interrupted.operand;
+        for (i, v) in [a, b, c].iter().enumerate(){
+            self.stack.set(i, *v);
         }
+        self.fstack = self.interrupted.fstack;
+        self.fp_error = self.interrupted.fp_error;
+        let shared = ERROR_FLAG | HALT_ON_ERROR_BIT;
+        self.status = (status as u32 as usize & !shared) | (self.status & shared);
+        Ok(())
+    }
+    
+    /// A ready high priority process interrupts a running low priority one
+    fn preempt(&mut self) -> Result<(), OpErr>{
+        if self.is_idle() || self.priority() == Priority::High
+            || self.get_front_pointer(Priority::High) == NOT_PROCESS_P{
+            return Ok(())
+        }
+        self.save_registers()?;
+        self.status &= ERROR_FLAG | HALT_ON_ERROR_BIT;
+        self.start_next_process()
     }
     
     fn update_wdesc(&mut self, wdesc: RTYPE){
@@ -419,64 +915,134 @@ impl Proc{
         self.workspace = wdesc & (!0b11);
     }
     
-    fn activate_process(&mut self){
-        // TODO clear Oreg
-        self.pc = self.mem.read(self.workspace - 4);
+    fn activate_process(&mut self) -> Result<(), OpErr>{
+        self.operand = 0;
+        self.pc = self.mem.read(self.workspace.wrapping_add(IPTR_S))?;
+        Ok(())
     }
     
-    pub fn run_process(&mut self, wdesc: RTYPE){
+    pub fn run_process(&mut self, wdesc: RTYPE) -> Result<(), OpErr>{
         let wpri = wdesc & 0b1;
         let waddress = wdesc & !0b11;
         
-        match self.priority(){
-            Priority::High => {
-                if wpri > 0{
-                    // Add low priority to queue
-                    if self.get_front_pointer(Priority::Low) == NOT_PROCESS_P{
-                        self.set_front_pointer(Priority::Low, waddress);
-                    }
-                    else{
-                        let bp = self.get_back_pointer(Priority::Low);
-                        self.mem.write(bp - 8, waddress);
-                    }
-                    self.set_back_pointer(Priority::Low, waddress);
-                }
-                else{
-                    // Adding high priority to queue
-                    if self.get_front_pointer(Priority::High) == NOT_PROCESS_P{
-                        self.set_front_pointer(Priority::High, waddress);
-                    }
-                    else{
-                        let bp = self.get_back_pointer(Priority::High);
-                        self.mem.write(bp - 8, waddress);
-                    }
-                    self.set_back_pointer(Priority::High, waddress);
-                }
-            },
-            Priority::Low => {
-                if wpri == 0{
-                    // Switch immediately to new high priority process
-                    self.save_registers();
-                    self.update_wdesc(wdesc);
-                    self.status = self.status & (ERROR_FLAG | HALT_ON_ERROR_BIT);
-                    self.activate_process();
-                }
+        if self.is_idle(){
+            // Nothing to interrupt, run it straight away
+            self.update_wdesc(waddress | wpri);
+            self.start_timeslice();
+            return self.activate_process();
+        }
+        
+        // A running low priority process is interrupted once the
+        // current instruction has finished
+        if wpri == 0{
+            self.enqueue(Priority::High, waddress)
+        }
+        else{
+            self.enqueue(Priority::Low, waddress)
+        }
+    }
+    
+    /// Copy a program into memory and point Iptr at its first byte
+    pub fn load_program(&mut self, address: ATYPE, program: &[u8]) -> Result<(), MemErr>{
+        let config = LoadConfig::new(address, self.workspace);
+        self.load_image(program, &config)?;
+        Ok(())
+    }
+    
+    /// Fetch the instruction at Iptr and execute it
+    /// Does nothing once the processor is halted, when idle time moves on to the next timer
+    pub fn step(&mut self) -> Result<(), ExecError>{
+        if self.halted{
+            return Ok(());
+        }
+        if self.is_idle(){
+            return self.idle_until_timer().and_then(|_| self.preempt()).map_err(|e| ExecError{
+                iptr: self.pc,
+                byte: 0,
+                op: Mnemonic::Unfetched,
+                cause: e
+            });
+        }
+        let instruction = match self.mem.read_byte(self.pc){
+            Ok(instruction) => instruction,
+            Err(e) => return Err(ExecError{
+                iptr: self.pc,
+                byte: 0,
+                op: Mnemonic::Unfetched,
+                cause: e.into()
+            })
+        };
+        self.run(instruction)
+    }
+    
+    /// Step until `done` is true, the processor halts or stalls,
+    /// or `max_steps` instructions have run
+    /// Returns the number of instructions executed
+    pub fn run_until<F: Fn(&Proc) -> bool>(&mut self, done: F, max_steps: usize) -> Result<usize, ExecError>{
+        let mut steps = 0;
+        while steps < max_steps && !self.halted && !self.is_stalled() && !done(self){
+            self.step()?;
+            steps += 1;
+        }
+        Ok(steps)
+    }
+    
+    /// Name the instruction byte, code is the full operand including prefixes
+    fn decode(&self, instruction: u8, code: RTYPE) -> Mnemonic{
+        let op = DirectOp::from_instruction(instruction);
+        if op == DirectOp::OPR{
+            match self.library.get_indirect_name(code as usize){
+                Some(name) => Mnemonic::Indirect(name, code as usize),
+                None => Mnemonic::Illegal(code)
             }
         }
+        else{
+            Mnemonic::Direct(op)
+        }
     }
     
-    pub fn run(&mut self, instruction: u8) -> Result<(), OpErr>{
+    /// Execute a single instruction byte, Iptr is advanced past it first
+    /// Traps, and errors that halt the processor, are returned
+    pub fn run(&mut self, instruction: u8) -> Result<(), ExecError>{
         let op = instruction >> 4;
         let v = instruction & 0xF;
+        let iptr = self.pc;
+        let code = self.operand.wrapping_add(v as RTYPE);
+        let cycles = self.fetch_cycles(iptr) + self.instruction_cycles(instruction, code);
+        let accesses = self.mem.external_accesses();
         
-        // TODO check how branch or others work
-        self.pc += 1;
+        self.pc = self.pc.wrapping_add(1);
         
         let result = match self.library.direct[op as usize].clone()(self, v){
             Ok(v) => v,
+            Err(e) if e == OpErr::IllegalOpcode || e == OpErr::Unimplemented => {
+                match self.illegal_policy{
+                    IllegalOpPolicy::Skip => OpVal::Null,
+                    IllegalOpPolicy::SetError => {
+                        self.throw_error(e);
+                        OpVal::Null
+                    },
+                    policy => {
+                        self.halted = policy == IllegalOpPolicy::Halt;
+                        return Err(ExecError{
+                            iptr,
+                            byte: instruction,
+                            op: self.decode(instruction, code),
+                            cause: e
+                        });
+                    }
+                }
+            },
+            Err(e) if e.is_trap() => {
+                return Err(ExecError{
+                    iptr,
+                    byte: instruction,
+                    op: self.decode(instruction, code),
+                    cause: e
+                });
+            },
             Err(e) => {
-                println!("Got error {:?}", e);
-                self.error = 1;
+                self.throw_error(e);
                 OpVal::Null
             }
         };
@@ -490,6 +1056,31 @@ impl Proc{
             }
             _ => ()
         };
+        
+        let cycles = cycles + self.wait_states(self.mem.external_accesses() - accesses);
+        let mut scheduled = Ok(());
+        if self.status & GO_TO_SNP_BIT != 0{
+            scheduled = self.start_next_process();
+        }
+        if let Err(e) = scheduled.and_then(|_| self.advance_time(cycles)).and_then(|_| self.preempt()){
+            return Err(ExecError{
+                iptr,
+                byte: instruction,
+                op: self.decode(instruction, code),
+                cause: e
+            });
+        }
+        
+        if self.halted{
+            if let Some(cause) = self.halt_cause.take(){
+                return Err(ExecError{
+                    iptr,
+                    byte: instruction,
+                    op: self.decode(instruction, code),
+                    cause
+                });
+            }
+        }
         Ok(())
     }
     
@@ -505,6 +1096,7 @@ impl Proc{
 #[cfg(test)]
 mod processor_tests{
     use super::*;
+    use super::fpu::Real;
     
     #[test]
     fn ldc(){
@@ -514,7 +1106,7 @@ mod processor_tests{
         let _ = proc.run(0x42);
         let _ = proc.run(0xD0);
         
-        assert_eq!(m.read(0x1000), 0x2);
+        assert_eq!(m.read(0x1000).unwrap(), 0x2);
     }
     
     #[test]
@@ -526,7 +1118,7 @@ mod processor_tests{
         let _ = proc.run(0x23);
         let _ = proc.run(0x42);
         let _ = proc.run(0xD0);
-        assert_eq!(m.read(0x1000), 0x432);
+        assert_eq!(m.read(0x1000).unwrap(), 0x432);
     }
     
     #[test]
@@ -538,7 +1130,7 @@ mod processor_tests{
         let _ = proc.run(0x83);
         let _ = proc.run(0xD0);
         
-        assert_eq!(m.read(0x1000), 0x9);
+        assert_eq!(m.read(0x1000).unwrap(), 0x9);
     }
     
     #[test]
@@ -550,6 +1142,1723 @@ mod processor_tests{
         let _ = proc.run(0x12);
         let _ = proc.run(0xD0);
         
-        assert_eq!(m.read(0x1000), 0x1008); 
+        assert_eq!(m.read(0x1000).unwrap(), 0x1008); 
+    }
+    
+    /// Repeatable pseudo random words, for tests over many operands
+    /// The top half of a 64 bit LCG, so the low bits are as random as the rest
+    fn random_words(seed: u32) -> impl FnMut() -> u32{
+        let mut state = seed as u64;
+        move ||{
+            state = state.wrapping_mul(6364136223846793005).wrapping_add(1442695040888963407);
+            (state >> 32) as u32
+        }
+    }
+    
+    /// Load a program at 0x100 and run it to its last byte
+    fn run_program(proc: &mut Proc, program: &[u8]){
+        proc.load_program(0x100, program).unwrap();
+        let end = 0x100 + program.len() as i32;
+        proc.run_until(|p| p.program_counter() == end, 1000).unwrap();
+        assert_eq!(proc.program_counter(), end);
+    }
+    
+    #[test]
+    fn step_fetches_from_memory(){
+        let mut proc = Proc::new(0x1000);
+        let m = proc.mem_reference();
+        
+        proc.load_program(0x100, &[0x24, 0x23, 0x42, 0xD0]).unwrap();
+        for _ in 0..4{
+            proc.step().unwrap();
+        }
+        assert_eq!(proc.program_counter(), 0x104);
+        assert_eq!(m.read(0x1000).unwrap(), 0x432);
+    }
+    
+    #[test]
+    fn jump(){
+        let mut proc = Proc::new(0x1000);
+        let m = proc.mem_reference();
+        
+        // j 2 skips over ldc 1, stl 0
+        run_program(&mut proc, &[0x47, 0xD0, 0x02, 0x41, 0xD0]);
+        assert_eq!(m.read(0x1000).unwrap(), 0x7);
+    }
+    
+    #[test]
+    fn conditional_jump(){
+        let mut proc = Proc::new(0x1000);
+        let m = proc.mem_reference();
+        
+        // Taken: A is zero and left on the stack
+        run_program(&mut proc, &[0x40, 0xA2, 0x41, 0xD0, 0xD1]);
+        assert_eq!(m.read(0x1004).unwrap(), 0);
+        assert_eq!(m.read(0x1000).unwrap(), 0);
+        
+        // Not taken: A is popped
+        run_program(&mut proc, &[0x45, 0x43, 0xA1, 0xD2]);
+        assert_eq!(m.read(0x1008).unwrap(), 0x5);
+    }
+    
+    #[test]
+    fn call_and_return(){
+        let mut proc = Proc::new(0x1000);
+        let m = proc.mem_reference();
+        
+        // call 1; j 4; ldc 9; stl 1; ret
+        run_program(&mut proc, &[0x91, 0x04, 0x49, 0xD1, 0x22, 0xF0]);
+        assert_eq!(proc.workspace_pointer(), 0x1000);
+        assert_eq!(m.read(0x1000 - 12).unwrap(), 0x9);
+        assert_eq!(m.read(0x1000 - 16).unwrap(), 0x101);
+    }
+    
+    #[test]
+    fn general_call(){
+        let mut proc = Proc::new(0x1000);
+        
+        // ldc 0x105; gcall; skips the final ldc 0
+        run_program(&mut proc, &[0x21, 0x20, 0x45, 0xF6, 0x40]);
+        assert_eq!(proc.get_reg(0), 0x104);
+    }
+    
+    #[test]
+    fn loop_end(){
+        let mut proc = Proc::new(0x1000);
+        let m = proc.mem_reference();
+        
+        run_program(&mut proc, &[
+            0x40, 0xD0, // index = 0
+            0x43, 0xD1, // count = 3
+            0x40, 0xD2, // total = 0
+            // loop:
+            0x72, 0x85, 0xD2, // total += 5
+            0x10, 0x47, 0x22, 0xF1 // lend back to loop
+        ]);
+        assert_eq!(m.read(0x1000).unwrap(), 2);
+        assert_eq!(m.read(0x1004).unwrap(), 0);
+        assert_eq!(m.read(0x1008).unwrap(), 15);
+    }
+    
+    #[test]
+    fn load_image(){
+        let mut proc = Proc::new(0x1000);
+        let m = proc.mem_reference();
+        
+        let config = LoadConfig::new(0x200, 0x2000).with_entry(0x202);
+        let range = proc.load_image(&[0x41, 0xD0, 0x42, 0xD0], &config).unwrap();
+        assert_eq!(range, 0x200..0x204);
+        assert_eq!(proc.program_counter(), 0x202);
+        assert_eq!(proc.workspace_pointer(), 0x2000);
+        assert_eq!(m.read_byte(0x200).unwrap(), 0x41);
+        
+        proc.run_until(|p| p.program_counter() == range.end, 10).unwrap();
+        assert_eq!(m.read(0x2000).unwrap(), 0x2);
+    }
+    
+    #[test]
+    fn load_file(){
+        let mut proc = Proc::new(0x1000);
+        let m = proc.mem_reference();
+        
+        let path = std::env::temp_dir().join("t800_load_file.bin");
+        std::fs::write(&path, [0x24, 0x23, 0x42, 0xD0]).unwrap();
+        let range = proc.load_file(&path, &LoadConfig::new(0x300, 0x1000)).unwrap();
+        std::fs::remove_file(&path).unwrap();
+        
+        assert_eq!(range, 0x300..0x304);
+        proc.run_until(|p| p.program_counter() == range.end, 10).unwrap();
+        assert_eq!(m.read(0x1000).unwrap(), 0x432);
     }
-}
\ No newline at end of file
+    
+    #[test]
+    fn load_assembled_image(){
+        let mut proc = Proc::new(0x1000);
+        let m = proc.mem_reference();
+        
+        let mut asm = crate::asm::Assemble::new();
+        asm.setup(&proc);
+        let image = asm.assemble("ldc 3\nldc 4\nadd\nstl 1 ; result\n").unwrap();
+        let range = proc.load_image(&image, &LoadConfig::new(0x400, 0x1000)).unwrap();
+        
+        proc.run_until(|p| p.program_counter() == range.end, 10).unwrap();
+        assert_eq!(m.read(0x1004).unwrap(), 7);
+    }
+    
+    /// Assemble a listing and run it from 0x100
+    fn run_asm(proc: &mut Proc, source: &str){
+        let mut asm = crate::asm::Assemble::new();
+        asm.setup(proc);
+        let image = asm.assemble(source).unwrap();
+        run_program(proc, &image);
+    }
+    
+    #[test]
+    fn add_overflow_sets_error(){
+        let mut proc = Proc::new(0x1000);
+        
+        run_asm(&mut proc, "ldc 1\nldc 2\nadd");
+        assert_eq!(proc.get_reg(0), 3);
+        assert!(!proc.error_flag());
+        
+        run_asm(&mut proc, "ldc 2147483647\nldc 1\nadd");
+        assert_eq!(proc.get_reg(0), i32::MIN);
+        assert!(proc.error_flag());
+        assert!(!proc.is_halted());
+    }
+    
+    #[test]
+    fn test_error(){
+        let mut proc = Proc::new(0x1000);
+        
+        run_asm(&mut proc, "testerr\nseterr\ntesterr\ntesterr");
+        assert_eq!(proc.get_stack(), vec![1, 0, 1]);
+        assert!(!proc.error_flag());
+    }
+    
+    #[test]
+    fn long_arithmetic(){
+        let mut proc = Proc::new(0x1000);
+        
+        // Carry and borrow in from C, out to B
+        run_asm(&mut proc, "ldc 1\nldc -1\nldc 0\nlsum");
+        assert_eq!(proc.get_stack()[..2], [0, 1]);
+        run_asm(&mut proc, "ldc 0\nldc 5\nldc 7\nldiff");
+        assert_eq!(proc.get_stack()[..2], [-2, 1]);
+        run_asm(&mut proc, "ldc 1\nldc 7\nldc 5\nldiff");
+        assert_eq!(proc.get_stack()[..2], [1, 0]);
+        
+        // Signed forms only set the error flag on overflow
+        run_asm(&mut proc, "ldc 1\nldc 2147483646\nldc 0\nladd");
+        assert_eq!(proc.get_reg(0), i32::MAX);
+        assert!(!proc.error_flag());
+        run_asm(&mut proc, "ldc 1\nldc 2147483647\nldc 0\nladd");
+        assert_eq!(proc.get_reg(0), i32::MIN);
+        assert!(proc.error_flag());
+        proc.clear_error();
+        run_asm(&mut proc, "ldc 1\nldc -2147483647\nldc 0\nlsub");
+        assert_eq!(proc.get_reg(0), i32::MIN);
+        assert!(!proc.error_flag());
+        run_asm(&mut proc, "ldc 1\nldc -2147483648\nldc 0\nlsub");
+        assert!(proc.error_flag());
+        proc.clear_error();
+        
+        // 0xFFFFFFFF * 0xFFFFFFFF + 0xFFFFFFFF
+        run_asm(&mut proc, "ldc -1\nldc -1\nldc -1\nlmul");
+        assert_eq!(proc.get_stack()[..2], [0, -1]);
+        
+        // 0x1_00000005 / 2
+        run_asm(&mut proc, "ldc 1\nldc 5\nldc 2\nldiv");
+        assert_eq!(proc.get_stack()[..2], [-2147483646, 1]);
+        assert!(!proc.error_flag());
+        run_asm(&mut proc, "ldc 2\nldc 5\nldc 2\nldiv");
+        assert!(proc.error_flag());
+        proc.clear_error();
+        
+        // Shifts of C:B
+        run_asm(&mut proc, "ldc 1\nldc -2147483648\nldc 4\nlshl");
+        assert_eq!(proc.get_stack()[..2], [0, 0x18]);
+        run_asm(&mut proc, "ldc 1\nldc 0\nldc 36\nlshr");
+        assert_eq!(proc.get_stack()[..2], [0, 0]);
+        run_asm(&mut proc, "ldc 16\nldc 0\nldc 36\nlshr");
+        assert_eq!(proc.get_stack()[..2], [1, 0]);
+        run_asm(&mut proc, "ldc 1\nldc 1\nldc 64\nlshl");
+        assert_eq!(proc.get_stack()[..2], [0, 0]);
+        
+        // norm leaves the shifted value in A and B and the count in C
+        run_asm(&mut proc, "ldc 1\nldc 0\nnorm");
+        assert_eq!(proc.get_stack(), vec![0, i32::MIN, 31]);
+        run_asm(&mut proc, "ldc 0\nldc 0\nnorm");
+        assert_eq!(proc.get_stack(), vec![0, 0, 64]);
+    }
+    
+    #[test]
+    fn crc_instructions(){
+        use crate::checksum::{crc32, crc_bytes};
+        use std::sync::OnceLock;
+        
+        // Unreflected CRCs without an initial value or final xor are what the
+        // instructions compute once the register is flushed. The crate only
+        // takes algorithms that live forever, so the generated ones are static.
+        static GENERATED: OnceLock<Vec<crc::Algorithm<u32>>> = OnceLock::new();
+        let generated = GENERATED.get_or_init(||{
+            let mut random = random_words(0x9E3779B9);
+            (0..8).map(|_| crc::Algorithm{ poly: random(), check: 0, residue: 0, ..crc::CRC_32_AIXM }).collect()
+        });
+        
+        let mut proc = Proc::new(0x1000);
+        let mut random = random_words(0x2545F491);
+        for algorithm in [&crc::CRC_32_AIXM, &crc::CRC_32_XFER].into_iter().chain(generated){
+            let expected = crc::Crc::<u32>::new(algorithm);
+            let generator = algorithm.poly as i32;
+            
+            // Whole words then a zero word to flush the register
+            for len in 0..4{
+                let words: Vec<i32> = (0..len).map(|_| random() as i32).collect();
+                let mut crc = 0;
+                for w in words.iter().chain([0].iter()){
+                    run_asm(&mut proc, &format!("ldc {}\nldc {}\nldc {}\ncrcword", w, crc, generator));
+                    crc = proc.get_reg(0);
+                }
+                let bytes: Vec<u8> = words.iter().flat_map(|w| w.to_be_bytes()).collect();
+                assert_eq!(crc as u32, expected.checksum(&bytes), "{:#x} over {} words", generator, len);
+            }
+            
+            // A byte at a time from the top of C
+            for len in 0..=9{
+                let bytes = &b"123456789"[..len];
+                let mut crc = 0;
+                for b in bytes{
+                    run_asm(&mut proc, &format!("ldc {}\nldc {}\nldc {}\ncrcbyte", (*b as i32) << 24, crc, generator));
+                    crc = proc.get_reg(0);
+                }
+                run_asm(&mut proc, &format!("ldc 0\nldc {}\nldc {}\ncrcword", crc, generator));
+                assert_eq!(proc.get_reg(0) as u32, expected.checksum(bytes), "{:#x} over {} bytes", generator, len);
+                assert_eq!(crc_bytes(algorithm.poly, bytes), expected.checksum(bytes));
+            }
+        }
+        assert_eq!(crc_bytes(crc::CRC_32_AIXM.poly, b"123456789"), crc::CRC_32_AIXM.check);
+        assert_eq!(crc32(b"123456789"), crc::CRC_32_ISO_HDLC.check);
+    }
+    
+    #[test]
+    fn bit_manipulation(){
+        let mut proc = Proc::new(0x1000);
+        let values = [0, 1, -1, i32::MIN, i32::MAX, 0x1234_5678, 0x5555_5555, -0x0F0F_0F10];
+        
+        for v in values{
+            run_asm(&mut proc, &format!("ldc 3\nldc {}\nbitcnt", v));
+            assert_eq!(proc.get_reg(0), 3 + v.count_ones() as i32);
+            
+            run_asm(&mut proc, &format!("ldc {}\nbitrevword", v));
+            let r = proc.get_reg(0);
+            for bit in 0..32{
+                assert_eq!((r >> bit) & 1, (v >> (31 - bit)) & 1);
+            }
+            run_asm(&mut proc, "bitrevword");
+            assert_eq!(proc.get_reg(0), v);
+            
+            // Bit i of the bottom n bits moves to bit n - 1 - i
+            for n in 0..=32{
+                run_asm(&mut proc, &format!("ldc {}\nldc {}\nbitrevnbits", v, n));
+                let r = proc.get_reg(0) as u32;
+                let mut expected = 0u32;
+                for bit in 0..n{
+                    expected |= ((v as u32 >> bit) & 1) << (n - 1 - bit);
+                }
+                assert_eq!(r, expected, "bitrevnbits {} of {:#x}", n, v);
+            }
+        }
+        
+        // Counts accumulate across words
+        run_asm(&mut proc, "ldc 0\nldc -1\nbitcnt\nldc 255\nbitcnt");
+        assert_eq!(proc.get_reg(0), 40);
+    }
+    
+    #[test]
+    fn block_moves(){
+        let mut proc = Proc::new(0x1000);
+        let mut m = proc.mem_reference();
+        m.write_bytes(0x3000, b"abcdefgh").unwrap();
+        
+        run_asm(&mut proc, "ldc 12288\nldc 12304\nldc 8\nmove");
+        for i in 0..8{
+            assert_eq!(m.read_byte(0x3010 + i).unwrap(), b"abcdefgh"[i as usize]);
+        }
+        
+        // Overlapping blocks copy as if through a buffer
+        run_asm(&mut proc, "ldc 12288\nldc 12290\nldc 6\nmove");
+        for i in 0..8{
+            assert_eq!(m.read_byte(0x3000 + i).unwrap(), b"ababcdef"[i as usize]);
+        }
+        
+        // A block running off the end of memory traps without writing anything
+        let mut asm = crate::asm::Assemble::new();
+        asm.setup(&proc);
+        let end = DRAM_SIZE as i32 - 4;
+        proc.load_program(0x100, &asm.assemble(&format!("ldc 12288\nldc {}\nldc 8\nmove", end)).unwrap()).unwrap();
+        let e = proc.run_until(|_| false, 20).unwrap_err();
+        assert_eq!(e.cause, OpErr::OutOfRange(end));
+        assert_eq!(m.read(end).unwrap(), 0);
+    }
+    
+    #[test]
+    fn block_moves_2d(){
+        let mut proc = Proc::new(0x1000);
+        let mut m = proc.mem_reference();
+        
+        // 3 rows of 4 bytes with a source stride of 8, the destination stride is 16
+        let rows: [[u8; 4]; 3] = [[1, 0, 2, 0], [0, 3, 0, 4], [5, 6, 7, 8]];
+        for (r, row) in rows.iter().enumerate(){
+            m.write_bytes(0x3000 + 8 * r as i32, row).unwrap();
+        }
+        let setup = |m: &mut Mem|{
+            m.write_bytes(0x4000, &[0xAA; 48]).unwrap();
+        };
+        let expected = |keep: fn(u8) -> bool, r: usize, i: usize| -> u8{
+            if i < 4 && keep(rows[r][i]) { rows[r][i] } else { 0xAA }
+        };
+        
+        for (op, keep) in [("move2dall", (|_| true) as fn(u8) -> bool), ("move2dnonzero", |v| v != 0), ("move2dzero", |v| v == 0)]{
+            setup(&mut m);
+            run_asm(&mut proc, &format!("ldc 8\nldc 16\nldc 3\nmove2dinit\nldc 12288\nldc 16384\nldc 4\n{}", op));
+            for r in 0..3{
+                for i in 0..16{
+                    assert_eq!(m.read_byte(0x4000 + (16 * r + i) as i32).unwrap(), expected(keep, r, i), "{} row {} byte {}", op, r, i);
+                }
+            }
+        }
+        
+        // The setup is kept for further moves
+        setup(&mut m);
+        run_asm(&mut proc, "ldc 12288\nldc 16384\nldc 2\nmove2dall");
+        assert_eq!(m.read_byte(0x4010).unwrap(), 0);
+        assert_eq!(m.read_byte(0x4011).unwrap(), 3);
+        assert_eq!(m.read_byte(0x4012).unwrap(), 0xAA);
+    }
+    
+    #[test]
+    fn software_floating_point(){
+        let mut proc = Proc::with_model(0x1000, Model::T414.descriptor()).unwrap();
+        
+        // Unpack into fraction, exponent and type
+        for (v, frac, exp, kind) in [(0.0f32, 0, 0, 0), (1.5, 0xC000_0000u32, 127, 2), (-2.0, 0x8000_0000, 128, 2),
+            (f32::from_bits(1), 0x100, 1, 1), (f32::INFINITY, 0, 255, 3)]{
+            run_asm(&mut proc, &format!("ldc 1\nldc {}\nunpacksn", v.to_bits() as i32));
+            assert_eq!(proc.get_stack(), vec![frac as i32, exp, 4 + kind], "unpacksn {}", v);
+        }
+        
+        // Unpacking then rounding with no guard bits gives back the magnitude
+        let mut words = random_words(0x2545F491);
+        let mut random = ||{
+            let seed = words();
+            f32::from_bits((seed & 0x807F_FFFF) | ((seed >> 8) % 100 + 30) << 23)
+        };
+        for _ in 0..200{
+            let v = random();
+            run_asm(&mut proc, &format!("ldc {}\nunpacksn\nldc 0\nroundsn", v.to_bits() as i32));
+            assert_eq!(proc.get_reg(0) as u32, v.abs().to_bits());
+        }
+        
+        // Multiply the fractions, normalise and round, as a T414 REAL32 multiply does
+        let unpack = |v: f32| -> (u32, i32){
+            let exp = (v.to_bits() >> 23 & 0xFF) as i32;
+            let mantissa = (v.to_bits() & 0x7F_FFFF) << 8;
+            if exp == 0 { (mantissa, 1) } else { (mantissa | 0x8000_0000, exp) }
+        };
+        for _ in 0..500{
+            let (x, y) = (random(), random());
+            let (fx, ex) = unpack(x);
+            let (fy, ey) = unpack(y);
+            run_asm(&mut proc, &format!("ldc {}\nstl 0\nldc 0\nldc {}\nldc {}\nlmul\nnorm\npostnormsn\nroundsn",
+                ex + ey - 126, fx as i32, fy as i32));
+            assert_eq!(proc.get_reg(0) as u32, (x * y).abs().to_bits(), "{} * {}", x, y);
+        }
+        
+        // Overflow rounds to infinity
+        run_asm(&mut proc, "ldc 300\nldc -1\nldc -1\nroundsn");
+        assert_eq!(proc.get_reg(0), 0x7F80_0000);
+        
+        run_asm(&mut proc, "ldinf\ncflerr");
+        assert_eq!(proc.get_reg(0), 0x7F80_0000);
+        assert!(proc.error_flag());
+        proc.clear_error();
+        run_asm(&mut proc, "ldc 1065353216\ncflerr");
+        assert!(!proc.error_flag());
+        
+        // Fractional multiply, 0.5 * 0.5 and -1 * -1
+        run_asm(&mut proc, "ldc 1073741824\nldc 1073741824\nfmul");
+        assert_eq!(proc.get_reg(0), 0x2000_0000);
+        assert!(!proc.error_flag());
+        run_asm(&mut proc, "ldc -2147483648\nldc -2147483648\nfmul");
+        assert!(proc.error_flag());
+    }
+    
+    #[test]
+    fn processor_models(){
+        // Assemble with every instruction available
+        let mut asm = crate::asm::Assemble::new();
+        asm.setup(&Proc::with_model(0x1000, ModelDescriptor{
+            soft_float: true,
+            extended: true,
+            ..Model::T805.descriptor()
+        }).unwrap());
+        
+        let programs = [("dup", "ldc 1\ndup"), ("fpu", "fpldzerosn\nfpldzerosn\nfpadd"), ("fpentry", "fpldzerosn\nfpuabs"),
+            ("soft", "ldinf\ncflerr"), ("bits", "ldc 1\nbitrevword"), ("t414", "ldc 1\nldc 2\nadd")];
+        for (model, available) in [(Model::T414, ["soft", "t414"].as_slice()), (Model::T425, &["dup", "soft", "bits", "t414"]),
+            (Model::T800, &["fpu", "fpentry", "bits", "t414"]), (Model::T805, &["dup", "fpu", "fpentry", "bits", "t414"])]{
+            for (name, source) in programs{
+                let mut proc = Proc::with_model(0x1000, model.descriptor()).unwrap();
+                let image = asm.assemble(source).unwrap();
+                proc.load_program(0x100, &image).unwrap();
+                let result = proc.run_until(|p| p.program_counter() == 0x100 + image.len() as i32, 20);
+                if available.contains(&name){
+                    assert!(result.is_ok(), "{} on {:?}", name, model);
+                }
+                else{
+                    assert_eq!(result.unwrap_err().cause, OpErr::IllegalOpcode, "{} on {:?}", name, model);
+                }
+            }
+        }
+        
+        let t414 = Proc::with_model(0x1000, Model::T414.descriptor()).unwrap();
+        assert_eq!(t414.model().on_chip_ram, 2048);
+        assert_eq!(t414.cycles_per_microsecond(), 20);
+        assert_eq!(Proc::with_model(0x1000, Model::T805.descriptor()).unwrap().cycles_per_microsecond(), 25);
+        assert_eq!(Proc::new(0x1000).model().model, Model::T800);
+        assert_eq!("t425".parse::<Model>(), Ok(Model::T425));
+        
+        // Descriptors that can't run are rejected
+        let t800 = Model::T800.descriptor();
+        let broken = [(ModelDescriptor{ clock_mhz: 0, ..t800.clone() }, ModelErr::ZeroClock),
+            (ModelDescriptor{ on_chip_ram: 4098, ..t800.clone() }, ModelErr::RamSize(4098)),
+            (ModelDescriptor{ mem_start: 0x1000, ..t800.clone() }, ModelErr::MemStart(0x1000)),
+            (ModelDescriptor{ mem_start: TPTR_LOC, ..t800.clone() }, ModelErr::MemStart(TPTR_LOC))];
+        for (model, e) in broken{
+            assert_eq!(Proc::with_model(0x1000, model).err(), Some(e));
+        }
+    }
+    
+    #[test]
+    fn assembler_rejects_what_the_model_lacks(){
+        use crate::asm::{AsmErr, Assemble};
+        
+        let mut asm = Assemble::new();
+        asm.setup(&Proc::with_model(0x1000, Model::T414.descriptor()).unwrap());
+        assert_eq!(asm.assemble("fpldzerosn\nfpldzerosn\nfpadd"), Err(AsmErr::UnknownOp(1, "fpldzerosn".to_string())));
+        assert_eq!(asm.assemble("ldc 1\n\nldc 2\nadd\nbitrevword"), Err(AsmErr::UnknownOp(5, "bitrevword".to_string())));
+        assert_eq!(asm.assemble("ldc 1\nadx"), Err(AsmErr::UnknownOp(2, "adx".to_string())));
+        assert_eq!(asm.assemble("ldc end"), Err(AsmErr::UndefinedLabel(1, "end".to_string())));
+        assert_eq!(asm.assemble("ldc 1\nldc"), Err(AsmErr::MissingOperand(2, "ldc".to_string())));
+        
+        // Labels name the offset of the next instruction
+        let mut asm = Assemble::new();
+        asm.setup(&Proc::new(0x1000));
+        assert_eq!(asm.assemble("ldc 1\nstart:\nldc start ; comment"), Ok(vec![0x41, 0x41]));
+    }
+    
+    #[test]
+    fn pop_and_timeslice(){
+        let mut proc = Proc::with_model(0x1000, Model::T805.descriptor()).unwrap();
+        run_asm(&mut proc, "ldc 1\nldc 2\npop");
+        assert_eq!(proc.get_reg(0), 1);
+        
+        // Only a low priority process with another waiting gives way
+        run_asm(&mut proc, "timeslice");
+        let mut proc = Proc::with_model(0x1001, Model::T805.descriptor()).unwrap();
+        proc.enqueue(Priority::Low, 0x2000).unwrap();
+        proc.mem.write(0x2000 + IPTR_S, 0x400).unwrap();
+        let mut asm = crate::asm::Assemble::new();
+        asm.setup(&proc);
+        proc.load_program(0x100, &asm.assemble("timeslice").unwrap()).unwrap();
+        proc.step().unwrap();
+        proc.step().unwrap();
+        assert_eq!(proc.program_counter(), 0x400);
+        assert_eq!(proc.get_front_pointer(Priority::Low), 0x1000);
+    }
+    
+    #[test]
+    fn halt_on_error(){
+        let mut proc = Proc::new(0x1000);
+        let m = proc.mem_reference();
+        
+        run_asm(&mut proc, "testhalterr\nsethalterr\ntesthalterr\nclrhalterr\ntesthalterr");
+        assert_eq!(proc.get_stack(), vec![0, 1, 0]);
+        
+        proc.set_halt_on_error(true);
+        proc.load_program(0x100, &[0x21, 0xF0, 0x47, 0xD0]).unwrap(); // seterr; ldc 7; stl 0
+        let e = proc.run_until(|_| false, 10).unwrap_err();
+        assert_eq!(e.cause, OpErr::Err);
+        assert_eq!(e.op, Mnemonic::Indirect("seterr".to_string(), 0x10));
+        assert!(proc.is_halted());
+        assert!(proc.error_flag());
+        assert_eq!(proc.program_counter(), 0x102);
+        assert_eq!(m.read(0x1000).unwrap(), 0);
+    }
+    
+    #[test]
+    fn stop_on_error(){
+        let mut proc = Proc::new(0x1000);
+        let m = proc.mem_reference();
+        
+        run_asm(&mut proc, "stoperr");
+        assert!(!proc.is_idle());
+        
+        run_asm(&mut proc, "seterr\nstoperr");
+        assert!(proc.is_idle());
+        assert_eq!(m.read(0x1000 - 4).unwrap(), 0x104);
+    }
+    
+    /// Run parent with a child process at workspace 0x2000 started by startp
+    /// until both have stopped
+    fn run_pair(proc: &mut Proc, parent: &str, child: &str){
+        let mut asm = crate::asm::Assemble::new();
+        asm.setup(proc);
+        let parent = asm.assemble(&format!("{}\nstopp", parent)).unwrap();
+        let child = asm.assemble(&format!("{}\nstopp", child)).unwrap();
+        let mut image = asm.assemble(&format!("ldc {}\nldc 8192\nstartp", parent.len())).unwrap();
+        image.extend(parent);
+        image.extend(child);
+        
+        proc.load_program(0x100, &image).unwrap();
+        proc.run_until(|_| false, 1000).unwrap();
+        assert!(proc.is_idle());
+    }
+    
+    #[test]
+    fn channel_receiver_first(){
+        let mut proc = Proc::new(0x1000);
+        let mut m = proc.mem_reference();
+        m.write(0x3000, NOT_PROCESS_P).unwrap();
+        
+        // in 4 bytes to 0x3100 from channel 0x3000
+        run_pair(&mut proc, "ldc 12544\nldc 12288\nldc 4\nin", "ldc 12288\nldc 42\noutword");
+        assert_eq!(m.read(0x3100).unwrap(), 42);
+        assert_eq!(m.read(0x3000).unwrap(), NOT_PROCESS_P);
+        assert_eq!(m.read(0x1000 + POINTER_S).unwrap(), 0x3100);
+    }
+    
+    #[test]
+    fn channel_sender_first(){
+        let mut proc = Proc::new(0x1000);
+        let mut m = proc.mem_reference();
+        m.write(0x3000, NOT_PROCESS_P).unwrap();
+        
+        run_pair(&mut proc, "ldc 12288\nldc 300\noutbyte", "ldc 12544\nldc 12288\nldc 1\nin");
+        assert_eq!(m.read_byte(0x3100).unwrap(), 44);
+        assert_eq!(m.read(0x3000).unwrap(), NOT_PROCESS_P);
+    }
+    
+    #[test]
+    fn alt_selects_ready_channel(){
+        let mut proc = Proc::new(0x1000);
+        let mut m = proc.mem_reference();
+        m.write(0x3000, NOT_PROCESS_P).unwrap();
+        
+        // Channel guard is ready so altwt does not wait, the skip guard is not selected
+        run_pair(&mut proc, "ldc 12288\nldc 42\noutword",
+            "alt\nldc 12288\nldc 1\nenbc\nldc 1\nenbs\naltwt\n\
+            ldc 12288\nldc 1\nldc 1\ndisc\nldc 1\nldc 2\ndiss\naltend\nldc 5\nldc 7");
+        assert_eq!(m.read(0x2000).unwrap(), 1);
+        assert_eq!(proc.get_stack(), vec![7, 0, 1]);
+        assert_eq!(m.read(0x3000).unwrap(), 0x1000);
+    }
+    
+    #[test]
+    fn alt_waits_for_sender(){
+        let mut proc = Proc::new(0x1000);
+        let mut m = proc.mem_reference();
+        m.write(0x3000, NOT_PROCESS_P).unwrap();
+        
+        // The ALT waits, the sender makes its guard ready and waits on the channel
+        run_pair(&mut proc, "alt\nldc 12288\nldc 1\nenbc\naltwt\n\
+            ldc 12288\nldc 1\nldc 0\ndisc\naltend\nldc 12544\nldc 12288\nldc 4\nin",
+            "ldc 12288\nldc 42\noutword");
+        assert_eq!(m.read(0x1000 + STATE_S).unwrap(), READY_P);
+        assert_eq!(m.read(0x3100).unwrap(), 42);
+        assert_eq!(m.read(0x3000).unwrap(), NOT_PROCESS_P);
+    }
+    
+    /// Step through a two byte stopp
+    fn stopp(proc: &mut Proc){
+        proc.step().unwrap();
+        proc.step().unwrap();
+    }
+    
+    #[test]
+    fn scheduler_prefers_high_priority(){
+        let mut proc = Proc::new(0x1000);
+        let mut m = proc.mem_reference();
+        m.write(0x3000 + IPTR_S, 0x100).unwrap();
+        m.write(0x4000 + IPTR_S, 0x100).unwrap();
+        
+        // Queue a low then a high priority process, each runs stopp
+        proc.load_program(0x100, &[0x21, 0xF5]).unwrap();
+        proc.run_process(0x3001).unwrap();
+        proc.run_process(0x4000).unwrap();
+        
+        stopp(&mut proc);
+        assert_eq!(proc.descriptor, 0x4000);
+        stopp(&mut proc);
+        assert_eq!(proc.descriptor, 0x3001);
+        assert_eq!(proc.program_counter(), 0x100);
+        stopp(&mut proc);
+        assert!(proc.is_idle());
+        assert_eq!(proc.get_front_pointer(Priority::High), NOT_PROCESS_P);
+        assert_eq!(proc.get_front_pointer(Priority::Low), NOT_PROCESS_P);
+        
+        // Scheduling while idle starts the process straight away
+        proc.run_process(0x3001).unwrap();
+        assert_eq!(proc.descriptor, 0x3001);
+        assert_eq!(proc.program_counter(), 0x102);
+    }
+    
+    #[test]
+    fn low_priority_queue_order(){
+        let mut proc = Proc::new(0x1001);
+        let mut m = proc.mem_reference();
+        for w in [0x2000, 0x3000, 0x4000]{
+            m.write(w + IPTR_S, 0x100).unwrap();
+        }
+        proc.load_program(0x100, &[0x21, 0xF5]).unwrap();
+        for w in [0x2001, 0x3001, 0x4001]{
+            proc.run_process(w).unwrap();
+        }
+        assert_eq!(m.read(0x2000 + LINK_S).unwrap(), 0x3000);
+        assert_eq!(m.read(0x3000 + LINK_S).unwrap(), 0x4000);
+        
+        for w in [0x2001, 0x3001, 0x4001]{
+            stopp(&mut proc);
+            assert_eq!(proc.descriptor, w);
+        }
+    }
+    
+    #[test]
+    fn timer_alt_keeps_earliest_time(){
+        let mut proc = Proc::new(0x1000);
+        let m = proc.mem_reference();
+        run_asm(&mut proc, "talt\nldc 50\nldc 1\nenbt\nldc 30\nldc 1\nenbt\nldc 10\nldc 0\nenbt");
+        assert_eq!(m.read(0x1000 + TLINK_S).unwrap(), TIME_SET_P);
+        assert_eq!(m.read(0x1000 + TIME_S).unwrap(), 30);
+    }
+    
+    #[test]
+    fn clocks_tick_with_cycles(){
+        let mut proc = Proc::new(0x1000);
+        proc.advance_time(200 * 64 * proc.cycles_per_microsecond()).unwrap();
+        assert_eq!(proc.get_clock_register(Priority::High), 200 * 64);
+        assert_eq!(proc.get_clock_register(Priority::Low), 200);
+        
+        run_asm(&mut proc, "ldc 1000\nsttimer\nldtimer");
+        assert_eq!(proc.get_reg(0), 1000);
+        assert_eq!(proc.get_clock_register(Priority::Low), 1000);
+    }
+    
+    #[test]
+    fn tin_waits_for_timer(){
+        let mut proc = Proc::new(0x1000);
+        run_asm(&mut proc, "ldtimer\nadc 100\nstl 1\nldl 1\ntin\nldtimer");
+        assert!(!proc.timers_pending());
+        assert!(proc.get_reg(0) - proc.mem.read(0x1004).unwrap() >= 0);
+        assert!(proc.cycles() >= 100 * proc.cycles_per_microsecond());
+        
+        // While it waits the process is at the front of the high priority queue
+        let mut asm = crate::asm::Assemble::new();
+        asm.setup(&proc);
+        proc.load_program(0x100, &asm.assemble("ldtimer\nadc 100\ntin").unwrap()).unwrap();
+        proc.run_until(|p| p.is_idle(), 10).unwrap();
+        assert_eq!(proc.mem.read(TPTR_LOC).unwrap(), 0x1000);
+        assert_eq!(proc.mem.read(TPTR_LOC + 4).unwrap(), NOT_PROCESS_P);
+        proc.run_until(|p| !p.is_idle(), 10).unwrap();
+        assert_eq!(proc.mem.read(TPTR_LOC).unwrap(), NOT_PROCESS_P);
+        
+        // A time in the past does not wait, ldc, pfix and tin take 1, 1 and 4 cycles
+        // plus fetching their word from external memory
+        let cycles = proc.cycles();
+        run_asm(&mut proc, "ldc 0\ntin");
+        assert_eq!(proc.cycles(), cycles + 6 + 2);
+    }
+    
+    #[test]
+    fn timers_wake_in_order(){
+        let mut proc = Proc::new(0x1000);
+        let m = proc.mem_reference();
+        
+        // Each process records the order it woke in Wptr[1]
+        let wake = "ldc 12288\nldnl 0\nadc 1\nstl 1\nldl 1\nldc 12288\nstnl 0";
+        run_pair(&mut proc, &format!("ldtimer\nadc 50\ntin\n{}", wake),
+            &format!("ldtimer\nadc 20\ntin\n{}", wake));
+        assert_eq!(m.read(0x2004).unwrap(), 1);
+        assert_eq!(m.read(0x1004).unwrap(), 2);
+        assert!(!proc.timers_pending());
+    }
+    
+    #[test]
+    fn timer_alt_times_out(){
+        let mut proc = Proc::new(0x1000);
+        let mut m = proc.mem_reference();
+        m.write(0x3000, NOT_PROCESS_P).unwrap();
+        
+        // Wait on a channel nobody sends on, or 30us
+        run_asm(&mut proc, "ldtimer\nadc 30\nstl 1\ntalt\nldc 12288\nldc 1\nenbc\n\
+            ldl 1\nldc 1\nenbt\ntaltwt\n\
+            ldc 12288\nldc 1\nldc 0\ndisc\nldl 1\nldc 1\nldc 1\ndist\naltend\nldc 5\nldc 7");
+        assert_eq!(m.read(0x1000).unwrap(), 1);
+        assert_eq!(m.read(0x3000).unwrap(), NOT_PROCESS_P);
+        assert_eq!(proc.get_reg(0), 7);
+        assert_eq!(proc.get_reg(1), 1);
+        assert!(!proc.timers_pending());
+    }
+    
+    #[test]
+    fn timer_alt_channel_wins(){
+        let mut proc = Proc::new(0x1000);
+        let mut m = proc.mem_reference();
+        m.write(0x3000, NOT_PROCESS_P).unwrap();
+        
+        // The sender arrives before the timeout, dist takes the ALT off the timer queue
+        run_pair(&mut proc, "ldtimer\nadc 1000\nstl 1\ntalt\nldc 12288\nldc 1\nenbc\n\
+            ldl 1\nldc 1\nenbt\ntaltwt\n\
+            ldc 12288\nldc 1\nldc 0\ndisc\nldl 1\nldc 1\nldc 1\ndist\naltend\n\
+            ldc 12544\nldc 12288\nldc 4\nin",
+            "ldc 12288\nldc 42\noutword");
+        assert_eq!(m.read(0x1000).unwrap(), 0);
+        assert_eq!(m.read(0x3100).unwrap(), 42);
+        assert!(!proc.timers_pending());
+        assert!(proc.get_clock_register(Priority::High) < 1000);
+    }
+    
+    #[test]
+    fn timeslicing(){
+        // Two low priority processes counting in Wptr[1] forever
+        let program = "ldc 0\nldc 8192\nstartp\nldl 1\nadc 1\nstl 1\nj -5";
+        
+        let mut proc = Proc::new(0x1001);
+        let m = proc.mem_reference();
+        let mut asm = crate::asm::Assemble::new();
+        asm.setup(&proc);
+        proc.load_program(0x100, &asm.assemble(program).unwrap()).unwrap();
+        proc.run_until(|_| false, 2000).unwrap();
+        assert!(m.read(0x1004).unwrap() > 0);
+        assert_eq!(m.read(0x2004).unwrap(), 0);
+        
+        let mut proc = Proc::new(0x1001);
+        let m = proc.mem_reference();
+        proc.set_timeslice(10);
+        proc.load_program(0x100, &asm.assemble(program).unwrap()).unwrap();
+        proc.run_until(|_| false, 2000).unwrap();
+        assert!(m.read(0x1004).unwrap() > 0);
+        assert!(m.read(0x2004).unwrap() > 0);
+    }
+    
+    #[test]
+    fn high_priority_preempts_low(){
+        let mut proc = Proc::new(0x1000);
+        let mut m = proc.mem_reference();
+        let mut asm = crate::asm::Assemble::new();
+        asm.setup(&proc);
+        
+        // Low priority loops forever with 1, 2, 3 on the stack
+        m.write_bytes(0x200, &asm.assemble("ldc 1\nldc 2\nldc 3\nj -2").unwrap()).unwrap();
+        m.write(0x2000 + IPTR_S, 0x200).unwrap();
+        proc.run_process(0x2001).unwrap();
+        
+        // High priority waits 5us, then interrupts it
+        proc.load_program(0x100, &asm.assemble("ldtimer\nadc 5\ntin\nldc 42\nstl 1\nstopp").unwrap()).unwrap();
+        proc.run_until(|p| p.descriptor == 0x2001, 10).unwrap();
+        proc.run_until(|p| p.descriptor == 0x1000, 1000).unwrap();
+        assert_eq!(m.read(REGISTER_CACHE).unwrap(), 0x2001);
+        
+        proc.run_until(|p| p.descriptor == 0x2001, 10).unwrap();
+        assert_eq!(m.read(REGISTER_CACHE).unwrap(), NOT_PROCESS_P);
+        assert_eq!(m.read(0x1004).unwrap(), 42);
+        assert_eq!(proc.get_stack(), vec![3, 2, 1]);
+        assert!(proc.program_counter() >= 0x203 && proc.program_counter() <= 0x205);
+    }
+    
+    #[test]
+    fn preemption_keeps_error_flag(){
+        let mut proc = Proc::new(0x1001);
+        let mut m = proc.mem_reference();
+        let mut asm = crate::asm::Assemble::new();
+        asm.setup(&proc);
+        
+        // Low priority starts a high priority process that sets the error flag
+        m.write_bytes(0x200, &asm.assemble("seterr\nstopp").unwrap()).unwrap();
+        m.write(0x2000 + IPTR_S, 0x200).unwrap();
+        let program = asm.assemble("ldc 7\nldc 8192\nrunp\nldc 5").unwrap();
+        let end = 0x100 + program.len() as i32;
+        proc.load_program(0x100, &program).unwrap();
+        proc.run_until(|p| p.program_counter() == end, 100).unwrap();
+        assert_eq!(proc.descriptor, 0x1001);
+        assert!(proc.error_flag());
+        assert_eq!(proc.get_stack()[0], 5);
+        assert_eq!(proc.get_stack()[1], 7);
+    }
+    
+    /// Write reals to memory at 0x3000 onwards, low word first
+    fn write_reals(proc: &mut Proc, values: &[Real]){
+        let mut address = 0x3000;
+        for v in values{
+            proc.write_real(address, *v).unwrap();
+            address += match v{
+                Real::Single(_) => 4,
+                Real::Double(_) => 8
+            };
+        }
+    }
+    
+    #[test]
+    fn fp_load_store_single(){
+        let mut proc = Proc::new(0x1000);
+        let m = proc.mem_reference();
+        write_reals(&mut proc, &[Real::Single(1.5), Real::Single(-2.25)]);
+        
+        run_asm(&mut proc, "ldc 12288\nfpldnlsn\nldc 12292\nfpldnlsn");
+        assert_eq!(proc.get_float_stack()[..2], [Real::Single(-2.25), Real::Single(1.5)]);
+        
+        run_asm(&mut proc, "ldc 12544\nfpstnlsn\nldc 12548\nfpstnlsn");
+        assert_eq!(m.read(0x3100).unwrap() as u32, (-2.25f32).to_bits());
+        assert_eq!(m.read(0x3104).unwrap() as u32, 1.5f32.to_bits());
+    }
+    
+    #[test]
+    fn fp_load_store_double(){
+        let mut proc = Proc::new(0x1000);
+        let m = proc.mem_reference();
+        write_reals(&mut proc, &[Real::Double(0.1)]);
+        
+        run_asm(&mut proc, "ldc 12288\nfpldnldb\nldc 12544\nfpstnldb");
+        let bits = 0.1f64.to_bits();
+        assert_eq!(m.read(0x3100).unwrap() as u32, bits as u32);
+        assert_eq!(m.read(0x3104).unwrap() as u32, (bits >> 32) as u32);
+        
+        run_asm(&mut proc, "fpldzerodb\nfpldzerosn");
+        assert_eq!(proc.get_float_stack()[..2], [Real::Single(0.0), Real::Double(0.0)]);
+    }
+    
+    #[test]
+    fn fp_indexed_loads(){
+        let mut proc = Proc::new(0x1000);
+        write_reals(&mut proc, &[Real::Single(1.0), Real::Single(2.0), Real::Double(3.0), Real::Double(4.0)]);
+        
+        // Singles from 0x3000, doubles from 0x3008
+        run_asm(&mut proc, "ldc 1\nldc 12288\nfpldnlsni\nldc 1\nldc 12296\nfpldnldbi");
+        assert_eq!(proc.get_float_stack()[..2], [Real::Double(4.0), Real::Single(2.0)]);
+    }
+    
+    #[test]
+    fn fp_arithmetic(){
+        let mut proc = Proc::new(0x1000);
+        write_reals(&mut proc, &[Real::Single(6.0), Real::Single(1.5), Real::Double(6.0), Real::Double(1.5)]);
+        let load = "ldc 12288\nfpldnlsn\nldc 12292\nfpldnlsn\n";
+        
+        for (op, result) in [("fpadd", 7.5), ("fpsub", 4.5), ("fpmul", 9.0), ("fpdiv", 4.0)]{
+            run_asm(&mut proc, &format!("{}{}", load, op));
+            assert_eq!(proc.get_float_stack()[0], Real::Single(result));
+            
+            run_asm(&mut proc, &format!("ldc 12296\nfpldnldb\nldc 12304\nfpldnldb\n{}", op));
+            assert_eq!(proc.get_float_stack()[0], Real::Double(result as f64));
+        }
+        
+        // Single length rounding
+        write_reals(&mut proc, &[Real::Single(1.0), Real::Single(3.0)]);
+        run_asm(&mut proc, &format!("{}fpdiv", load));
+        assert_eq!(proc.get_float_stack()[0], Real::Single(1.0f32 / 3.0));
+    }
+    
+    #[test]
+    fn fp_load_and_operate(){
+        let mut proc = Proc::new(0x1000);
+        write_reals(&mut proc, &[Real::Single(2.0), Real::Single(3.0), Real::Double(5.0)]);
+        
+        run_asm(&mut proc, "ldc 12288\nfpldnlsn\nldc 12292\nfpldnladdsn\nldc 12292\nfpldnlmulsn");
+        assert_eq!(proc.get_float_stack()[0], Real::Single(15.0));
+        
+        run_asm(&mut proc, "fpldzerodb\nldc 12296\nfpldnladddb\nldc 12296\nfpldnlmuldb");
+        assert_eq!(proc.get_float_stack()[..2], [Real::Double(25.0), Real::Single(15.0)]);
+    }
+    
+    #[test]
+    fn fp_stack_manipulation(){
+        let mut proc = Proc::new(0x1000);
+        write_reals(&mut proc, &[Real::Single(1.0), Real::Single(2.0)]);
+        
+        run_asm(&mut proc, "ldc 12288\nfpldnlsn\nldc 12292\nfpldnlsn\nfprev");
+        assert_eq!(proc.get_float_stack()[..2], [Real::Single(1.0), Real::Single(2.0)]);
+        
+        run_asm(&mut proc, "fpdup");
+        assert_eq!(proc.get_float_stack(), vec![Real::Single(1.0), Real::Single(1.0), Real::Single(2.0)]);
+    }
+    
+    #[test]
+    fn fp_comparisons(){
+        let mut proc = Proc::new(0x1000);
+        write_reals(&mut proc, &[Real::Single(1.0), Real::Single(2.0), Real::Single(f32::NAN), Real::Single(f32::INFINITY)]);
+        let load = |a: i32, b: i32| format!("ldc {}\nfpldnlsn\nldc {}\nfpldnlsn\n", 0x3000 + 4 * a, 0x3000 + 4 * b);
+        
+        // FB > FA
+        run_asm(&mut proc, &format!("{}fpgt", load(1, 0)));
+        assert_eq!(proc.get_reg(0), 1);
+        run_asm(&mut proc, &format!("{}fpgt", load(0, 1)));
+        assert_eq!(proc.get_reg(0), 0);
+        run_asm(&mut proc, &format!("{}fpeq", load(1, 1)));
+        assert_eq!(proc.get_reg(0), 1);
+        run_asm(&mut proc, &format!("{}fpeq", load(2, 2)));
+        assert_eq!(proc.get_reg(0), 0);
+        
+        run_asm(&mut proc, &format!("{}fpordered", load(0, 1)));
+        assert_eq!(proc.get_reg(0), 1);
+        run_asm(&mut proc, &format!("{}fpordered", load(2, 1)));
+        assert_eq!(proc.get_reg(0), 0);
+        run_asm(&mut proc, &format!("{}fpnan", load(0, 2)));
+        assert_eq!(proc.get_reg(0), 1);
+        run_asm(&mut proc, &format!("{}fpnotfinite", load(0, 3)));
+        assert_eq!(proc.get_reg(0), 1);
+        run_asm(&mut proc, &format!("{}fpnotfinite", load(3, 0)));
+        assert_eq!(proc.get_reg(0), 0);
+    }
+    
+    #[test]
+    fn fpentry_operations(){
+        let mut proc = Proc::new(0x1000);
+        write_reals(&mut proc, &[Real::Single(-3.0)]);
+        
+        run_asm(&mut proc, "ldc 12288\nfpldnlsn\nfpuabs");
+        assert_eq!(proc.get_float_stack()[0], Real::Single(3.0));
+        run_asm(&mut proc, "fpumulby2\nfpumulby2\nfpudivby2");
+        assert_eq!(proc.get_float_stack()[0], Real::Single(6.0));
+        run_asm(&mut proc, "fpuexpinc32");
+        assert_eq!(proc.get_float_stack()[0], Real::Single(6.0 * 4294967296.0));
+        run_asm(&mut proc, "fpuexpdec32");
+        assert_eq!(proc.get_float_stack()[0], Real::Single(6.0));
+        
+        // fpentry with an unknown code
+        proc.load_program(0x100, &[0x40, 0x2A, 0xFB]).unwrap();
+        let e = proc.run_until(|_| false, 10).unwrap_err();
+        assert_eq!(e.cause, OpErr::IllegalOpcode);
+    }
+    
+    #[test]
+    fn fp_rounding_modes(){
+        let mut proc = Proc::new(0x1000);
+        write_reals(&mut proc, &[Real::Single(1.0), Real::Single(3.0), Real::Single(-1.0)]);
+        let third = 1.0f32 / 3.0;
+        
+        // 1/3 rounds up to nearest
+        for (mode, result) in [("fpurn", third), ("fpurz", third.next_down()), ("fpurm", third.next_down()), ("fpurp", third)]{
+            run_asm(&mut proc, &format!("ldc 12288\nfpldnlsn\nldc 12292\nfpldnlsn\n{}\nfpdiv", mode));
+            assert_eq!(proc.get_float_stack()[0], Real::Single(result));
+        }
+        for (mode, result) in [("fpurz", -third.next_down()), ("fpurm", -third), ("fpurp", -third.next_down())]{
+            run_asm(&mut proc, &format!("ldc 12296\nfpldnlsn\nldc 12292\nfpldnlsn\n{}\nfpdiv", mode));
+            assert_eq!(proc.get_float_stack()[0], Real::Single(result));
+        }
+        
+        // The mode only lasts for one operation
+        run_asm(&mut proc, "fpurz\nldc 12288\nfpldnlsn\nldc 12292\nfpldnlsn\nfpdiv\nldc 12288\nfpldnlsn\nldc 12292\nfpldnlsn\nfpdiv");
+        assert_eq!(proc.get_float_stack()[0], Real::Single(third));
+        assert_eq!(proc.get_float_stack()[1], Real::Single(third.next_down()));
+        
+        // x - x is negative zero only when rounding towards minus infinity
+        run_asm(&mut proc, "ldc 12288\nfpldnlsn\nfpdup\nfpsub");
+        assert!(proc.get_float_stack()[0].to_f64().is_sign_positive());
+        run_asm(&mut proc, "ldc 12288\nfpldnlsn\nfpdup\nfpurm\nfpsub");
+        assert!(proc.get_float_stack()[0].to_f64().is_sign_negative());
+        
+        // A tiny addend only shows when rounding away from it
+        write_reals(&mut proc, &[Real::Double(1.0), Real::Double(1e-300)]);
+        run_asm(&mut proc, "ldc 12288\nfpldnldb\nldc 12296\nfpldnldb\nfpurp\nfpadd");
+        assert_eq!(proc.get_float_stack()[0], Real::Double(1.0f64.next_up()));
+        
+        // Overflow goes to infinity or the largest finite value, and is an error either way
+        write_reals(&mut proc, &[Real::Single(f32::MAX)]);
+        run_asm(&mut proc, "fpuclrerr\nldc 12288\nfpldnlsn\nfpurz\nfpumulby2");
+        assert_eq!(proc.get_float_stack()[0], Real::Single(f32::MAX));
+        assert!(proc.fp_error_flag());
+        run_asm(&mut proc, "fpuclrerr\nldc 12288\nfpldnlsn\nfpdup\nfpurm\nfpadd");
+        assert_eq!(proc.get_float_stack()[0], Real::Single(f32::MAX));
+        assert!(proc.fp_error_flag());
+        run_asm(&mut proc, "ldc 12288\nfpldnlsn\nfpumulby2");
+        assert_eq!(proc.get_float_stack()[0], Real::Single(f32::INFINITY));
+    }
+    
+    #[test]
+    fn rounding_matches_exact_results(){
+        use super::rounding::{self, RoundMode};
+        
+        // Single length operands are exact in double length for these ranges
+        let mut words = random_words(0x12345678);
+        let mut random = ||{
+            let bits = words();
+            f32::from_bits((bits & 0x807F_FFFF) | ((bits % 16 + 120) << 23))
+        };
+        let modes = [RoundMode::Nearest, RoundMode::Zero, RoundMode::Plus, RoundMode::Minus];
+        for _ in 0..20000{
+            let (b, a) = (random(), random());
+            let exact = [b as f64 + a as f64, b as f64 * a as f64];
+            for (i, op) in [rounding::add::<f32>, rounding::mul::<f32>].iter().enumerate(){
+                let r = modes.map(|m| op(b, a, m) as f64);
+                assert!(r[3] <= exact[i] && exact[i] <= r[2]);
+                assert!(r[2] == r[3] || (r[3] as f32).next_up() as f64 == r[2]);
+                assert_eq!(r[1], if exact[i] < 0.0 { r[2] } else { r[3] });
+                assert_eq!(r[0], (exact[i] as f32) as f64);
+            }
+            
+            // Products that underflow into the subnormal range
+            let (tb, ta) = (b * 1e-20, a * 1e-20);
+            let exact = tb as f64 * ta as f64;
+            let r = modes.map(|m| rounding::mul(tb, ta, m) as f64);
+            assert!(r[3] <= exact && exact <= r[2]);
+            assert_eq!(r[0], (exact as f32) as f64);
+            
+            // Squares of single length roots are exact in double length
+            let s = modes.map(|m| rounding::sqrt(b.abs(), m) as f64);
+            assert_eq!(s[0] as f32, b.abs().sqrt());
+            assert!(s[3] * s[3] <= b.abs() as f64 && b.abs() as f64 <= s[2] * s[2]);
+            
+            // Sign of the exact remainder b - q * a
+            let q = modes.map(|m| rounding::div(b, a, m) as f64);
+            assert_eq!(q[0] as f32, b / a);
+            assert!(((-q[3]).mul_add(a as f64, b as f64) / a as f64) >= 0.0);
+            assert!(((-q[2]).mul_add(a as f64, b as f64) / a as f64) <= 0.0);
+        }
+    }
+    
+    #[test]
+    fn fp_error_flag(){
+        let mut proc = Proc::new(0x1000);
+        write_reals(&mut proc, &[Real::Single(1.0), Real::Single(0.0)]);
+        
+        run_asm(&mut proc, "fpuseterr\nfptesterr\nfptesterr");
+        assert_eq!(proc.stack.a(), 1);
+        assert_eq!(proc.stack.b(), 0);
+        
+        // Division by zero
+        run_asm(&mut proc, "ldc 12288\nfpldnlsn\nldc 12292\nfpldnlsn\nfpdiv");
+        assert!(proc.fp_error_flag());
+        assert!(!proc.error_flag());
+        run_asm(&mut proc, "fpchkerr");
+        assert!(proc.error_flag());
+        run_asm(&mut proc, "fpuclrerr");
+        assert!(!proc.fp_error_flag());
+        
+        // Saved with the rest of a low priority process
+        proc.fp_error = true;
+        proc.save_registers().unwrap();
+        proc.fp_error = false;
+        proc.restore_registers().unwrap();
+        assert!(proc.fp_error_flag());
+    }
+    
+    #[test]
+    fn fp_conversions(){
+        let mut proc = Proc::new(0x1000);
+        let m = proc.mem_reference();
+        proc.mem.write(0x3000, 16777217).unwrap();
+        proc.mem.write(0x3004, -1).unwrap();
+        
+        // 2^24 + 1 is not a single
+        run_asm(&mut proc, "ldc 12288\nfpi32tor32");
+        assert_eq!(proc.get_float_stack()[0], Real::Single(16777216.0));
+        run_asm(&mut proc, "ldc 12288\nfpurp\nfpi32tor32");
+        assert_eq!(proc.get_float_stack()[0], Real::Single(16777218.0));
+        run_asm(&mut proc, "ldc 12288\nfpi32tor64\nldc 12292\nfpi32tor64\nldc 12292\nfpb32tor64");
+        assert_eq!(proc.get_float_stack(), vec![Real::Double(4294967295.0), Real::Double(-1.0), Real::Double(16777217.0)]);
+        
+        // Integer rounding in each mode
+        write_reals(&mut proc, &[Real::Double(-2.5)]);
+        for (mode, result) in [("fpurn", -2.0), ("fpurz", -2.0), ("fpurp", -2.0), ("fpurm", -3.0)]{
+            run_asm(&mut proc, &format!("ldc 12288\nfpldnldb\n{}\nfpint", mode));
+            assert_eq!(proc.get_float_stack()[0], Real::Double(result));
+        }
+        run_asm(&mut proc, "ldc 12288\nfpldnldb\nfprtoi32\nldc 12304\nfpstnli32");
+        assert_eq!(m.read(0x3010).unwrap(), -2);
+        assert!(!proc.fp_error_flag());
+        
+        // Range checks
+        write_reals(&mut proc, &[Real::Double(2147483648.0), Real::Double(-9.3e18)]);
+        run_asm(&mut proc, "ldc 12288\nfpldnldb\nfpuchki64");
+        assert!(!proc.fp_error_flag());
+        run_asm(&mut proc, "fpuchki32");
+        assert!(proc.fp_error_flag());
+        run_asm(&mut proc, "fpuclrerr\nldc 12296\nfpldnldb\nfpuchki64");
+        assert!(proc.fp_error_flag());
+        
+        // Length conversions
+        let v = 0.1f64;
+        write_reals(&mut proc, &[Real::Double(v), Real::Double(1e300)]);
+        run_asm(&mut proc, "ldc 12288\nfpldnldb\nfpur64tor32");
+        assert_eq!(proc.get_float_stack()[0], Real::Single(v as f32));
+        run_asm(&mut proc, "ldc 12288\nfpldnldb\nfpurz\nfpur64tor32");
+        assert_eq!(proc.get_float_stack()[0], Real::Single((v as f32).next_down()));
+        run_asm(&mut proc, "fpur32tor64");
+        assert_eq!(proc.get_float_stack()[0], Real::Double((v as f32).next_down() as f64));
+        run_asm(&mut proc, "fpuclrerr\nldc 12296\nfpldnldb\nfpurz\nfpur64tor32");
+        assert_eq!(proc.get_float_stack()[0], Real::Single(f32::MAX));
+        assert!(proc.fp_error_flag());
+        run_asm(&mut proc, "fpuclrerr\nldc 12296\nfpldnldb\nfpur64tor32");
+        assert_eq!(proc.get_float_stack()[0], Real::Single(f32::INFINITY));
+        assert!(proc.fp_error_flag());
+    }
+    
+    #[test]
+    fn fp_square_root(){
+        let mut proc = Proc::new(0x1000);
+        write_reals(&mut proc, &[Real::Single(2.0), Real::Double(2.0), Real::Single(-1.0)]);
+        
+        run_asm(&mut proc, "ldc 12288\nfpldnlsn\nfpusqrtfirst");
+        assert_eq!(proc.get_float_stack()[1..], [Real::Single(2.0), Real::Single(2.0)]);
+        run_asm(&mut proc, "fpusqrtstep\nfpusqrtstep");
+        assert!((proc.get_float_stack()[0].to_f64() - 2f64.sqrt()).abs() < 1e-3);
+        run_asm(&mut proc, "fpusqrtlast");
+        assert_eq!(proc.get_float_stack()[0], Real::Single(2f32.sqrt()));
+        
+        let sequence = "ldc 12292\nfpldnldb\nfpusqrtfirst\nfpusqrtstep\nfpusqrtstep\nfpusqrtstep\nfpusqrtstep\nfpusqrtstep\n";
+        run_asm(&mut proc, &format!("{}fpusqrtlast", sequence));
+        assert_eq!(proc.get_float_stack()[0], Real::Double(2f64.sqrt()));
+        
+        // sqrt(2) rounds up to nearest
+        run_asm(&mut proc, &format!("{}fpurm\nfpusqrtlast", sequence));
+        assert_eq!(proc.get_float_stack()[0], Real::Double(2f64.sqrt().next_down()));
+        
+        // The full sequence is correctly rounded in every mode
+        for v in [3.0, 0.1, 1e300, 5e-324, 123456789.0]{
+            write_reals(&mut proc, &[Real::Single(v as f32), Real::Double(v)]);
+            for (mode, round) in [("", RoundMode::Nearest), ("fpurz\n", RoundMode::Zero), ("fpurp\n", RoundMode::Plus), ("fpurm\n", RoundMode::Minus)]{
+                run_asm(&mut proc, &format!("{}{}fpusqrtlast", sequence, mode));
+                assert_eq!(proc.get_float_stack()[0], Real::Double(super::rounding::sqrt(v, round)), "{} {:?}", v, round);
+                run_asm(&mut proc, &format!("ldc 12288\nfpldnlsn\nfpusqrtfirst\nfpusqrtstep\nfpusqrtstep\n{}fpusqrtlast", mode));
+                assert_eq!(proc.get_float_stack()[0], Real::Single(super::rounding::sqrt(v as f32, round)), "{} {:?}", v, round);
+            }
+        }
+        
+        write_reals(&mut proc, &[Real::Single(2.0), Real::Double(2.0), Real::Single(-1.0)]);
+        
+        // Without the steps the approximation is too rough
+        run_asm(&mut proc, "ldc 12292\nfpldnldb\nfpusqrtfirst\nfpusqrtlast");
+        let rough = proc.get_float_stack()[0].to_f64();
+        assert_ne!(rough, 2f64.sqrt());
+        assert!((rough - 2f64.sqrt()).abs() < 1e-2);
+        
+        run_asm(&mut proc, "ldc 12300\nfpldnlsn\nfpusqrtfirst\nfpusqrtstep\nfpusqrtstep\nfpusqrtlast");
+        assert!(proc.get_float_stack()[0].is_nan());
+        assert!(proc.fp_error_flag());
+    }
+    
+    #[test]
+    fn fp_remainder(){
+        let mut proc = Proc::new(0x1000);
+        
+        // Runs the remainder sequence, returning the number of steps taken
+        fn remainder(proc: &mut Proc, b: Real, a: Real) -> usize{
+            write_reals(proc, &[b, a]);
+            let (lb, la) = match (b, a){
+                (Real::Single(_), _) => ("ldc 12288\nfpldnlsn", "ldc 12292\nfpldnlsn"),
+                _ => ("ldc 12288\nfpldnldb", "ldc 12296\nfpldnldb")
+            };
+            run_asm(proc, &format!("{}\n{}\nfpremfirst", lb, la));
+            let mut steps = 1;
+            while proc.stack.a() == 0{
+                assert_eq!(proc.get_float_stack()[0], a);
+                run_asm(proc, "fpremstep");
+                steps += 1;
+            }
+            steps
+        }
+        
+        assert_eq!(remainder(&mut proc, Real::Single(10.0), Real::Single(3.0)), 1);
+        assert_eq!(proc.get_float_stack()[0], Real::Single(1.0));
+        
+        // Ties go to the even quotient
+        remainder(&mut proc, Real::Single(11.0), Real::Single(2.0));
+        assert_eq!(proc.get_float_stack()[0], Real::Single(-1.0));
+        remainder(&mut proc, Real::Single(-9.0), Real::Single(2.0));
+        assert_eq!(proc.get_float_stack()[0], Real::Single(-1.0));
+        
+        // 2^100 takes several steps
+        assert!(remainder(&mut proc, Real::Double(2f64.powi(100)), Real::Double(3.0)) > 2);
+        assert_eq!(proc.get_float_stack()[0], Real::Double(1.0));
+        assert!(!proc.fp_error_flag());
+        
+        remainder(&mut proc, Real::Double(1.0), Real::Double(0.0));
+        assert!(proc.get_float_stack()[0].is_nan());
+        assert!(proc.fp_error_flag());
+        
+        // Truncating conversion
+        write_reals(&mut proc, &[Real::Double(0.1)]);
+        run_asm(&mut proc, "ldc 12288\nfpldnldb\nfpunoround");
+        assert_eq!(proc.get_float_stack()[0], Real::Single(0.1f32.next_down()));
+    }
+    
+    #[test]
+    fn execution_errors(){
+        let mut proc = Proc::new(0x1000);
+        
+        // ldc 2; ldnl 0 is not word aligned
+        proc.load_program(0x100, &[0x42, 0x30]).unwrap();
+        proc.step().unwrap();
+        let e = proc.step().unwrap_err();
+        assert_eq!(e, ExecError{
+            iptr: 0x101,
+            byte: 0x30,
+            op: Mnemonic::Direct(DirectOp::LDNL),
+            cause: OpErr::Unaligned(2)
+        });
+        
+        // ldc -4; ldnl 0 is outside of memory
+        proc.load_program(0x100, &[0x60, 0x4C, 0x30]).unwrap();
+        let e = proc.run_until(|_| false, 10).unwrap_err();
+        assert_eq!(e.iptr, 0x102);
+        assert_eq!(e.cause, OpErr::OutOfRange(-4));
+        assert!(!proc.is_halted());
+        
+        // Fetching outside of memory
+        proc.load_program(0x100, &[0x60, 0x4C, 0xF6]).unwrap();
+        let e = proc.run_until(|_| false, 10).unwrap_err();
+        assert_eq!(e.iptr, -4);
+        assert_eq!(e.op, Mnemonic::Unfetched);
+    }
+    
+    #[test]
+    fn overflow_error_when_halting(){
+        let mut proc = Proc::new(0x1000);
+        proc.set_halt_on_error(true);
+        
+        // ldc MostPos; adc 1
+        proc.load_program(0x100, &[0x27, 0x2F, 0x2F, 0x2F, 0x2F, 0x2F, 0x2F, 0x4F, 0x81]).unwrap();
+        let e = proc.run_until(|_| false, 20).unwrap_err();
+        assert_eq!(e.cause, OpErr::Overflow);
+        assert_eq!(e.iptr, 0x108);
+        assert_eq!(e.to_string(), "Overflow at 0x108 (0x81 adc)");
+    }
+    
+    #[test]
+    fn illegal_opcode_traps(){
+        let mut proc = Proc::new(0x1000);
+        
+        // opr 0x11 does not exist
+        proc.load_program(0x100, &[0x21, 0xF1, 0x41]).unwrap();
+        let e = proc.run_until(|_| false, 10).unwrap_err();
+        assert_eq!(e.iptr, 0x101);
+        assert_eq!(e.op, Mnemonic::Illegal(0x11));
+        assert_eq!(e.cause, OpErr::IllegalOpcode);
+        assert!(!proc.is_halted());
+        
+        // Negative operands are never operations
+        proc.load_program(0x100, &[0x60, 0xF0]).unwrap();
+        let e = proc.run_until(|_| false, 10).unwrap_err();
+        assert_eq!(e.op, Mnemonic::Illegal(-16));
+    }
+    
+    /// T800 secondary instructions from the published instruction set
+    const T800_OPERATIONS: [(usize, &str); 124] = [
+        (0x00, "rev"), (0x01, "lb"), (0x02, "bsub"), (0x03, "endp"), (0x04, "diff"), (0x05, "add"), (0x06, "gcall"),
+        (0x07, "in"), (0x08, "prod"), (0x09, "gt"), (0x0A, "wsub"), (0x0B, "out"), (0x0C, "sub"), (0x0D, "startp"),
+        (0x0E, "outbyte"), (0x0F, "outword"), (0x10, "seterr"), (0x12, "resetch"), (0x13, "csub0"), (0x15, "stopp"),
+        (0x16, "ladd"), (0x17, "stlb"), (0x18, "sthf"), (0x19, "norm"), (0x1A, "ldiv"), (0x1B, "ldpi"), (0x1C, "stlf"),
+        (0x1D, "xdble"), (0x1E, "ldpri"), (0x1F, "rem"), (0x20, "ret"), (0x21, "lend"), (0x22, "ldtimer"),
+        (0x29, "testerr"), (0x2A, "testpranal"), (0x2B, "tin"), (0x2C, "div"), (0x2E, "dist"), (0x2F, "disc"),
+        (0x30, "diss"), (0x31, "lmul"), (0x32, "not"), (0x33, "xor"), (0x34, "bcnt"), (0x35, "lshr"), (0x36, "lshl"),
+        (0x37, "lsum"), (0x38, "lsub"), (0x39, "runp"), (0x3A, "xword"), (0x3B, "sb"), (0x3C, "gajw"), (0x3D, "savel"),
+        (0x3E, "saveh"), (0x3F, "wcnt"), (0x40, "shr"), (0x41, "shl"), (0x42, "mint"), (0x43, "alt"), (0x44, "altwt"),
+        (0x45, "altend"), (0x46, "and"), (0x47, "enbt"), (0x48, "enbc"), (0x49, "enbs"), (0x4A, "move"), (0x4B, "or"),
+        (0x4C, "csngl"), (0x4D, "ccnt1"), (0x4E, "talt"), (0x4F, "ldiff"), (0x50, "sthb"), (0x51, "taltwt"),
+        (0x52, "sum"), (0x53, "mul"), (0x54, "sttimer"), (0x55, "stoperr"), (0x56, "cword"), (0x57, "clrhalterr"),
+        (0x58, "sethalterr"), (0x59, "testhalterr"), (0x5B, "move2dinit"), (0x5C, "move2dall"), (0x5D, "move2dnonzero"),
+        (0x5E, "move2dzero"), (0x74, "crcword"), (0x75, "crcbyte"), (0x76, "bitcnt"), (0x77, "bitrevword"),
+        (0x78, "bitrevnbits"), (0x82, "fpldnldbi"), (0x83, "fpchkerr"), (0x84, "fpstnldb"), (0x86, "fpldnlsni"),
+        (0x87, "fpadd"), (0x88, "fpstnlsn"), (0x89, "fpsub"), (0x8A, "fpldnldb"), (0x8B, "fpmul"), (0x8C, "fpdiv"),
+        (0x8E, "fpldnlsn"), (0x8F, "fpremfirst"), (0x90, "fpremstep"), (0x91, "fpnan"), (0x92, "fpordered"),
+        (0x93, "fpnotfinite"), (0x94, "fpgt"), (0x95, "fpeq"), (0x96, "fpi32tor32"), (0x98, "fpi32tor64"),
+        (0x9A, "fpb32tor64"), (0x9C, "fptesterr"), (0x9D, "fprtoi32"), (0x9E, "fpstnli32"), (0x9F, "fpldzerosn"),
+        (0xA0, "fpldzerodb"), (0xA1, "fpint"), (0xA3, "fpdup"), (0xA4, "fprev"), (0xA6, "fpldnladddb"),
+        (0xA8, "fpldnlmuldb"), (0xAA, "fpldnladdsn"), (0xAB, "fpentry"), (0xAC, "fpldnlmulsn")
+    ];
+    
+    /// Operations selected by fpentry
+    const T800_FPENTRY: [(usize, &str); 19] = [
+        (0x01, "fpusqrtfirst"), (0x02, "fpusqrtstep"), (0x03, "fpusqrtlast"), (0x04, "fpurp"), (0x05, "fpurm"),
+        (0x06, "fpurz"), (0x07, "fpur32tor64"), (0x08, "fpur64tor32"), (0x09, "fpuexpdec32"), (0x0A, "fpuexpinc32"),
+        (0x0B, "fpuabs"), (0x0D, "fpunoround"), (0x0E, "fpuchki32"), (0x0F, "fpuchki64"), (0x11, "fpudivby2"),
+        (0x12, "fpumulby2"), (0x22, "fpurn"), (0x23, "fpuseterr"), (0x9C, "fpuclrerr")
+    ];
+    
+    #[test]
+    fn t800_opcode_map(){
+        let proc = Proc::new(0x1000);
+        let mut codes: Vec<usize> = proc.library.get_indirect_codes().collect();
+        codes.sort();
+        assert_eq!(codes, T800_OPERATIONS.map(|(code, _)| code));
+        for (code, name) in T800_OPERATIONS{
+            assert_eq!(proc.library.get_indirect_name(code).as_deref(), Some(name), "opcode {:#x}", code);
+        }
+        
+        let mut fpentry = proc.get_fpentry_ops();
+        fpentry.sort_by_key(|(_, code)| *code);
+        assert_eq!(fpentry, T800_FPENTRY.map(|(code, name)| (name.to_string(), code)));
+    }
+    
+    #[test]
+    fn instruction_table(){
+        // Every entry is installed on some model, with its own code
+        let models = [Model::T414, Model::T425, Model::T800, Model::T805].map(|m| Proc::with_model(0x1000, m.descriptor()).unwrap());
+        for table in [&info::INDIRECT_INSTRUCTIONS[..], &info::FPENTRY_INSTRUCTIONS[..]]{
+            for pair in table.windows(2){
+                assert!(pair[0].code < pair[1].code, "{} and {}", pair[0].mnemonic, pair[1].mnemonic);
+            }
+            for entry in table{
+                assert!(models.iter().any(|p| p.instruction_set().contains(&entry)), "{} is never installed", entry.mnemonic);
+            }
+        }
+        
+        // Each model has exactly what the table allows it
+        for p in &models{
+            let set = p.instruction_set();
+            assert_eq!(set.len(), info::DIRECT_INSTRUCTIONS.len() + p.library.indirect_id.len() + p.library.fpentry_id.len());
+            assert!(set.iter().all(|e| e.availability.on(p.model())));
+        }
+        assert_eq!(models[2].instruction_set().len(), 16 + T800_OPERATIONS.len() + T800_FPENTRY.len());
+        assert!(models[0].indirect_info(0x87).is_none());
+        assert_eq!(models[0].indirect_info(0x63).unwrap().mnemonic, "unpacksn");
+        assert_eq!(models[2].fpentry_info(0x22).unwrap().mnemonic, "fpurn");
+        assert_eq!(DirectOp::from_instruction(0x24).info().mnemonic, "pfix");
+    }
+    
+    #[test]
+    fn stnl_and_gajw(){
+        let mut proc = Proc::new(0x1000);
+        let m = proc.mem_reference();
+        
+        // stnl takes both the address and the value
+        run_asm(&mut proc, "ldc 5\nldc 7\nldc 8192\nstnl 1");
+        assert_eq!(m.read(0x2004).unwrap(), 7);
+        assert_eq!(proc.get_reg(0), 5);
+        
+        // gajw swaps A and Wptr, and back again
+        run_asm(&mut proc, "ldc 12288\ngajw");
+        assert_eq!(proc.workspace, 0x3000);
+        assert_eq!(proc.get_reg(0), 0x1000);
+        run_asm(&mut proc, "gajw");
+        assert_eq!(proc.workspace, 0x1000);
+        assert_eq!(proc.get_reg(0), 0x3000);
+    }
+    
+    #[test]
+    fn stack_effects_match_the_table(){
+        // Every model's operations on one processor, each run on a fresh one
+        let model = ModelDescriptor{ soft_float: true, extended: true, ..Model::T805.descriptor() };
+        let (a, b, c) = (0x3000, 0x2000, 0x1800);
+        for entry in Proc::with_model(0x1000, model.clone()).unwrap().instruction_set(){
+            // These run another operation, which has its own entry
+            if entry.mnemonic == "opr" || entry.mnemonic == "fpentry"{
+                continue;
+            }
+            let mut proc = Proc::with_model(0x1000, model.clone()).unwrap();
+            // Empty channels at the addresses in A and B
+            proc.mem.write(a, NOT_PROCESS_P).unwrap();
+            proc.mem.write(b, NOT_PROCESS_P).unwrap();
+            for (i, v) in [a, b, c].iter().enumerate(){
+                proc.stack.set(i, *v);
+            }
+            
+            let mut program = Vec::new();
+            let mut pops = entry.pops as usize;
+            match entry.encoding{
+                Encoding::Direct => program.push(((entry.code as u8) << 4) | 1),
+                Encoding::Indirect => program.extend(crate::asm::prefix_constant(0xF, entry.code as i32)),
+                Encoding::FpEntry => {
+                    // fpentry takes the code the ldc before it loaded
+                    run_program(&mut proc, &crate::asm::prefix_constant(0x4, entry.code as i32));
+                    program.extend(crate::asm::prefix_constant(0xF, info::lookup("fpentry").unwrap().code as i32));
+                    pops += 1;
+                }
+            }
+            let before = proc.get_stack();
+            proc.load_program(0x100, &program).unwrap();
+            for byte in &program{
+                proc.run(*byte).unwrap_or_else(|e| panic!("{}: {}", entry.mnemonic, e));
+            }
+            
+            // Popping leaves C in place, pushing moves the rest down
+            let mut expected = before;
+            for _ in 0..pops{
+                expected = vec![expected[1], expected[2], expected[2]];
+            }
+            let pushes = entry.pushes as usize;
+            assert_eq!(proc.get_stack()[pushes..], expected[..3 - pushes], "{}", entry.mnemonic);
+        }
+    }
+    
+    #[test]
+    #[should_panic(expected = "shr is Indirect 0x40 in the instruction table, not FpEntry")]
+    fn table_encodings_are_checked(){
+        let mut proc = Proc::new(0x1000);
+        proc.library = ProcLibrary::new();
+        proc.library.define_fpentry("shr", |_p| Ok(OpVal::Null));
+    }
+    
+    #[test]
+    fn disassembly(){
+        let proc = Proc::new(0x1000);
+        let source = "ldc 300\nldc -20\nadd\nfpurz\nfpadd\nj -4\nopr 511\nldc 99\nfpentry";
+        let mut asm = crate::asm::Assemble::new();
+        asm.setup(&proc);
+        let image = asm.assemble(source).unwrap();
+        let lines = crate::asm::disassemble(&proc, &image);
+        assert_eq!(lines.join("\n"), source);
+        
+        let mut asm = crate::asm::Assemble::new();
+        asm.setup(&proc);
+        assert_eq!(asm.assemble(&lines.join("\n")).unwrap(), image);
+    }
+    
+    #[test]
+    fn cycles_follow_the_instruction_table(){
+        let mut proc = Proc::new(0x1000);
+        let cycles = proc.cycles();
+        // ldc, ldc, pfix, mul, then ldc, pfix, fpentry and the fpurz it runs,
+        // with wait states for fetching both words from external memory
+        run_asm(&mut proc, "ldc 3\nldc 4\nmul\nfpurz");
+        assert_eq!(proc.cycles() - cycles, 1 + 1 + 1 + 38 + 1 + 1 + 1 + 1 + 2 * 2);
+    }
+    
+    #[test]
+    fn cycles_depend_on_operands(){
+        let mut proc = Proc::new(0x1000);
+        let base = proc.model().external_wait_states;
+        
+        // Shifts take a cycle for each place
+        run_asm(&mut proc, "ldc 1\nldc 5\nshl");
+        let short = proc.cycles();
+        let mut proc = Proc::new(0x1000);
+        run_asm(&mut proc, "ldc 1\nldc 12\nshl");
+        assert_eq!(proc.cycles() - short, 7);
+        
+        // prod takes a cycle for each bit of the multiplier
+        let mut proc = Proc::new(0x1000);
+        run_asm(&mut proc, "ldc 3\nldc 1\nprod");
+        let short = proc.cycles();
+        let mut proc = Proc::new(0x1000);
+        run_asm(&mut proc, "ldc 3\nldc 15\nprod");
+        assert_eq!(proc.cycles() - short, 3);
+        
+        // Each word moved in external memory is read and written once
+        let mut proc = Proc::new(0x1000);
+        let cycles = proc.cycles();
+        run_asm(&mut proc, "ldc 8192\nldc 8448\nldc 16\nmove");
+        // Twelve bytes of code over three words, with four words read and written
+        let program = 4 + 4 + 2 + 1 + 8;
+        let moved = 2 * 4 + 8 * base;
+        assert_eq!(proc.cycles() - cycles, program + moved + 3 * base);
+    }
+    
+    #[test]
+    fn on_chip_ram_has_no_wait_states(){
+        let mut proc = Proc::new(0x1000);
+        let m = proc.mem_reference();
+        assert!(m.is_on_chip(MOST_NEG));
+        assert!(!m.is_on_chip(MOST_NEG + proc.model().on_chip_ram as i32));
+        assert!(!m.is_on_chip(0x1000));
+        
+        let accesses = proc.mem.external_accesses();
+        proc.mem.write(0x1000, 1).unwrap();
+        proc.mem.read(0x1000).unwrap();
+        assert_eq!(proc.mem.external_accesses(), accesses + 2);
+        
+        proc.mem.write(EVENT_CHANNEL, NOT_PROCESS_P).unwrap();
+        proc.mem.read(TPTR_LOC).unwrap();
+        assert_eq!(proc.mem.external_accesses(), accesses + 2);
+        
+        // Faulting accesses aren't charged, nor are those of other handles
+        assert!(proc.mem.read(-4).is_err());
+        assert!(proc.mem.copy(0x1000, -4, 8).is_err());
+        m.read(0x1000).unwrap();
+        assert_eq!(proc.mem.external_accesses(), accesses + 2);
+        
+        // A debugger reading memory while the processor runs doesn't slow it
+        // down, the word at 0x100 has already been fetched
+        run_asm(&mut proc, "ldc 1");
+        let cycles = proc.cycles();
+        proc.load_program(0x100, &[0x41]).unwrap();
+        m.read(0x2000).unwrap();
+        proc.step().unwrap();
+        assert_eq!(proc.cycles() - cycles, 1);
+    }
+    
+    #[test]
+    fn memory_is_allocated_on_first_touch(){
+        // Hundreds of processors only hold the pages they use
+        let procs: Vec<Proc> = (0..200).map(|_| Proc::new(0x1000)).collect();
+        for proc in &procs{
+            assert!(proc.mem_reference().touched_pages().len() <= 1);
+        }
+        
+        let mut proc = Proc::new(0x1000);
+        let mut m = proc.mem_reference();
+        let untouched = m.touched_pages();
+        assert_eq!(m.read(0x0800_0000).unwrap(), 0);
+        assert_eq!(m.touched_pages(), untouched);
+        
+        // The program, its workspace, on-chip RAM and a word either side of a page boundary
+        run_asm(&mut proc, "ldc 1\nstl 1");
+        m.write(MOST_NEG + 8, 3).unwrap();
+        m.write_bytes(0x5000 - 2, &[1, 2, 3, 4]).unwrap();
+        assert_eq!(m.read(0x5000).unwrap(), 0x0403);
+        assert_eq!(m.touched_pages(), vec![MOST_NEG, 0, 0x1000, 0x4000, 0x5000]);
+        
+        // Moving untouched memory onto untouched memory allocates nothing
+        m.copy(0x0100_0000, 0x0200_0000, 0x0100_0000).unwrap();
+        m.copy_if(0x0100_0000, 0x0300_0000, 0x10_0000, |v| v == 0).unwrap();
+        assert_eq!(m.touched_pages(), vec![MOST_NEG, 0, 0x1000, 0x4000, 0x5000]);
+        
+        // Overlapping moves across pages, either way, copy as if through a buffer
+        let block: Vec<u8> = (0..3 * PAGE_SIZE as u32).map(|i| (i % 251) as u8).collect();
+        for (source, dest) in [(0x10_0000, 0x10_0123), (0x20_0123, 0x20_0000)]{
+            m.write_bytes(source, &block).unwrap();
+            m.copy(source, dest, block.len() as u32).unwrap();
+            for (i, v) in block.iter().enumerate(){
+                assert_eq!(m.read_byte(dest + i as i32).unwrap(), *v, "{:#x} byte {}", dest, i);
+            }
+        }
+    }
+    
+    #[test]
+    fn signed_memory_map(){
+        let mut proc = Proc::new(0x1000);
+        let mut m = proc.mem_reference();
+        let on_chip_end = MOST_NEG + proc.model().on_chip_ram as i32;
+        
+        // Channels, timer queues and the interrupted registers start empty
+        for word in [LINK_OUTPUT, LINK_OUTPUT + 12, LINK_INPUT, LINK_INPUT + 12, EVENT_CHANNEL, TPTR_LOC, TPTR_LOC + 4, REGISTER_CACHE]{
+            assert_eq!(m.read(word).unwrap(), NOT_PROCESS_P, "{:#x}", word);
+        }
+        assert!(proc.model().mem_start >= RESERVED_END);
+        m.write(MOST_NEG, 7).unwrap();
+        m.write(on_chip_end - 4, 8).unwrap();
+        assert_eq!(m.read(MOST_NEG).unwrap(), 7);
+        assert_eq!(m.read(on_chip_end - 4).unwrap(), 8);
+        
+        // Between on-chip RAM and external memory there is nothing
+        assert_eq!(m.read(on_chip_end), Err(MemErr::OutOfRange(on_chip_end)));
+        assert_eq!(m.write_byte(-1, 0), Err(MemErr::OutOfRange(-1)));
+        assert_eq!(m.copy(on_chip_end - 4, 0x1000, 8), Err(MemErr::OutOfRange(on_chip_end - 4)));
+        assert_eq!(m.copy(0x1000, i32::MAX - 3, 8), Err(MemErr::OutOfRange(i32::MAX - 3)));
+        
+        // A process running entirely in on-chip RAM
+        let mem_start = proc.model().mem_start;
+        proc.load_image(&[0x45, 0xD1, 0x71], &LoadConfig::new(mem_start, mem_start + 0x100)).unwrap();
+        proc.run_until(|p| p.program_counter() == mem_start + 3, 10).unwrap();
+        assert_eq!(m.read(mem_start + 0x104).unwrap(), 5);
+        assert_eq!(proc.get_reg(0), 5);
+        
+        // External memory straight after on-chip RAM
+        let mut model = Model::T800.descriptor();
+        model.external_start = on_chip_end;
+        model.external_ram = 0x1000;
+        let proc = Proc::with_model(mem_start, model).unwrap();
+        let mut m = proc.mem_reference();
+        assert_eq!(m.map(), MemMap{ on_chip: 4096, external_start: on_chip_end, external_size: 0x1000 });
+        m.write_bytes(on_chip_end - 2, &[1, 2, 3, 4]).unwrap();
+        m.copy(on_chip_end - 2, on_chip_end + 0x100, 4).unwrap();
+        assert_eq!(m.read(on_chip_end + 0x100).unwrap(), 0x0403_0201);
+        assert!(m.read(0x1000).is_err());
+        
+        // Iptr and Wptr wrap from MostPos around to on-chip RAM
+        let mut model = Model::T800.descriptor();
+        model.external_start = i32::MAX - 0xFFF;
+        model.external_ram = 0x1000;
+        let mut proc = Proc::with_model(mem_start, model).unwrap();
+        proc.load_program(i32::MAX, &[0x41]).unwrap();
+        proc.step().unwrap();
+        assert_eq!(proc.program_counter(), MOST_NEG);
+        // ret, with the return address at the top of external memory
+        proc.load_program(mem_start, &[0x22, 0xF0]).unwrap();
+        proc.mem.write(i32::MAX - 3, 0x1234).unwrap();
+        proc.update_wdesc(i32::MAX - 3);
+        proc.step().unwrap();
+        proc.step().unwrap();
+        assert_eq!(proc.program_counter(), 0x1234);
+        assert_eq!(proc.workspace, MOST_NEG + 12);
+    }
+    
+    #[test]
+    #[should_panic(expected = "stub is not in the instruction table")]
+    fn operations_missing_from_the_table_are_rejected(){
+        let mut proc = Proc::new(0x1000);
+        proc.library.define_indirect("stub", |_p| Ok(OpVal::Null));
+    }
+    
+    #[test]
+    #[should_panic(expected = "Instruction enbc defined twice")]
+    fn duplicate_names_are_rejected(){
+        let mut proc = Proc::new(0x1000);
+        proc.library.define_indirect("enbc", |_p| Ok(OpVal::Null));
+    }
+    
+    #[test]
+    fn word_operations(){
+        let mut proc = Proc::new(0x1000);
+        run_asm(&mut proc, "ldc -2147483648\nldc 1\ndiff");
+        assert_eq!(proc.get_reg(0), i32::MAX);
+        assert!(!proc.error_flag());
+        
+        // 127 fits in a byte, 128 doesn't
+        run_asm(&mut proc, "ldc -128\nldc 128\ncword");
+        assert_eq!(proc.get_reg(0), -128);
+        assert!(!proc.error_flag());
+        run_asm(&mut proc, "ldc 128\nldc 128\ncword");
+        assert!(proc.error_flag());
+        
+        let mut proc = Proc::with_model(0x1000, Model::T805.descriptor()).unwrap();
+        run_asm(&mut proc, "ldc 3\nldc 256\nwsubdb\nldmemstartval\nlddevid");
+        assert_eq!(proc.get_stack(), vec![10, 0x8000_0070u32 as i32, 280]);
+    }
+    
+    #[test]
+    fn unimplemented_opcode_traps(){
+        let mut proc = Proc::new(0x1000);
+        
+        // testpranal standing in for an operation still to be written
+        proc.library = ProcLibrary::new();
+        proc.library.define_indirect("testpranal", |_p| Err(OpErr::Unimplemented));
+        proc.load_program(0x100, &[0x22, 0xFA]).unwrap();
+        let e = proc.run_until(|_| false, 10).unwrap_err();
+        assert_eq!(e.op, Mnemonic::Indirect("testpranal".to_string(), 0x2A));
+        assert_eq!(e.cause, OpErr::Unimplemented);
+    }
+    
+    #[test]
+    fn illegal_opcode_policy(){
+        let program = [0x21, 0xF1, 0x45, 0xD0];
+        
+        let mut proc = Proc::new(0x1000);
+        proc.set_illegal_policy(IllegalOpPolicy::Halt);
+        proc.load_program(0x100, &program).unwrap();
+        assert!(proc.run_until(|_| false, 10).is_err());
+        assert!(proc.is_halted());
+        assert_eq!(proc.program_counter(), 0x102);
+        
+        let mut proc = Proc::new(0x1000);
+        proc.set_illegal_policy(IllegalOpPolicy::SetError);
+        run_program(&mut proc, &program);
+        assert!(proc.error_flag());
+        assert_eq!(proc.mem_reference().read(0x1000).unwrap(), 5);
+        
+        let mut proc = Proc::new(0x1000);
+        proc.set_illegal_policy(IllegalOpPolicy::Skip);
+        run_program(&mut proc, &program);
+        assert!(!proc.error_flag());
+        assert_eq!(proc.mem_reference().read(0x1000).unwrap(), 5);
+    }
+    
+    #[test]
+    fn random_programs_do_not_panic(){
+        // The programs are the same on every run
+        let mut next = random_words(0x1234_5678);
+        
+        for _ in 0..2000{
+            let mut proc = Proc::new(0x1000);
+            proc.set_illegal_policy(IllegalOpPolicy::Skip);
+            
+            // Random operands followed by a random operation
+            let mut program = Vec::new();
+            for _ in 0..3{
+                let value = match next() % 3{
+                    0 => (next() % 64) as i32,
+                    1 => next() as i32,
+                    _ => i32::MIN
+                };
+                program.extend(crate::asm::prefix_constant(0x4, value));
+            }
+            program.extend(crate::asm::prefix_constant(0xF, (next() % 0x100) as i32));
+            program.extend((0..16).map(|_| next() as u8));
+            
+            proc.load_program(0x100, &program).unwrap();
+            for _ in 0..32{
+                let _ = proc.step();
+            }
+        }
+    }
+}
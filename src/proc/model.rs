@@ -0,0 +1,136 @@
+/* Transputer models and the differences between them */
+
+use std::str::FromStr;
+
+use crate::mem::{MemMap, DRAM_SIZE, MOST_NEG, RESERVED_END};
+
+/// Transputer part
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Model{
+    /// 32 bit, software floating point support
+    T414,
+    /// T414 with the extended instruction set
+    T425,
+    /// Floating point unit, no software floating point support
+    T800,
+    /// T800 with the extended instruction set
+    T805
+}
+
+/// What a processor provides, built from a Model and adjustable before
+/// creating the processor, for example for a faster speed grade
+#[derive(Debug, Clone, PartialEq)]
+pub struct ModelDescriptor{
+    pub model: Model,
+    /// Bytes of on-chip RAM
+    pub on_chip_ram: usize,
+    /// Processor clock
+    pub clock_mhz: u64,
+    /// First address of external memory. 0 by default so programs and
+    /// workspaces keep their small positive addresses, MostNeg + on_chip_ram
+    /// puts it straight after on-chip RAM as on a board without aliasing
+    pub external_start: i32,
+    /// Bytes of external memory
+    pub external_ram: usize,
+    /// Extra cycles for each access to external memory, on-chip RAM has none
+    pub external_wait_states: u64,
+    /// First free word after the reserved on-chip locations
+    pub mem_start: i32,
+    /// Loaded by lddevid on the models that have it
+    pub device_id: i32,
+    /// fpentry and the fp instructions
+    pub fpu: bool,
+    /// unpacksn, postnormsn, roundsn, ldinf, cflerr and fmul
+    pub soft_float: bool,
+    /// CRC, bit manipulation and 2D block moves, missing from the T414
+    pub block_and_bit_ops: bool,
+    /// dup, pop, timeslice, lddevid and others added with the T425 and T805
+    pub extended: bool
+}
+
+impl Model{
+    pub fn descriptor(self) -> ModelDescriptor{
+        let (on_chip_ram, clock_mhz, mem_start, device_id) = match self{
+            Model::T414 => (2 * 1024, 20, 0x8000_0048u32 as i32, 0),
+            Model::T425 => (4 * 1024, 25, 0x8000_0070u32 as i32, 0),
+            Model::T800 => (4 * 1024, 20, 0x8000_0070u32 as i32, 0),
+            Model::T805 => (4 * 1024, 25, 0x8000_0070u32 as i32, 10)
+        };
+        ModelDescriptor{
+            model: self,
+            on_chip_ram,
+            clock_mhz,
+            // Not after on-chip RAM, see external_start
+            external_start: 0,
+            external_ram: DRAM_SIZE,
+            // The fastest external memory cycle is 3 processor cycles
+            external_wait_states: 2,
+            mem_start,
+            device_id,
+            fpu: matches!(self, Model::T800 | Model::T805),
+            soft_float: matches!(self, Model::T414 | Model::T425),
+            block_and_bit_ops: self != Model::T414,
+            extended: matches!(self, Model::T425 | Model::T805)
+        }
+    }
+}
+
+/// Why a descriptor can't be built into a processor
+#[derive(Debug, Clone, PartialEq)]
+pub enum ModelErr{
+    /// Time only passes with a running clock
+    ZeroClock,
+    /// RAM comes in whole words within the 32 bit address space
+    RamSize(usize),
+    /// MemStart has to be a word of on-chip RAM after the reserved words
+    MemStart(i32)
+}
+
+impl ModelDescriptor{
+    /// Check the descriptor describes a processor that can run
+    pub fn validate(&self) -> Result<(), ModelErr>{
+        if self.clock_mhz == 0{
+            return Err(ModelErr::ZeroClock);
+        }
+        for size in [self.on_chip_ram, self.external_ram]{
+            if size % 4 != 0 || u32::try_from(size).is_err(){
+                return Err(ModelErr::RamSize(size));
+            }
+        }
+        let offset = self.mem_start.wrapping_sub(MOST_NEG) as u32 as usize;
+        let reserved = RESERVED_END.wrapping_sub(MOST_NEG) as usize;
+        if self.mem_start % 4 != 0 || offset < reserved || offset >= self.on_chip_ram{
+            return Err(ModelErr::MemStart(self.mem_start));
+        }
+        Ok(())
+    }
+    
+    /// Where on-chip and external RAM are
+    pub fn mem_map(&self) -> MemMap{
+        MemMap{
+            on_chip: self.on_chip_ram as u32,
+            external_start: self.external_start,
+            external_size: self.external_ram as u32
+        }
+    }
+}
+
+impl FromStr for Model{
+    type Err = String;
+
+    fn from_str(name: &str) -> Result<Self, Self::Err>{
+        match name.to_uppercase().as_str(){
+            "T414" => Ok(Model::T414),
+            "T425" => Ok(Model::T425),
+            "T800" => Ok(Model::T800),
+            "T805" => Ok(Model::T805),
+            _ => Err(format!("Unknown model {}", name))
+        }
+    }
+}
+
+impl Default for ModelDescriptor{
+    fn default() -> Self{
+        Model::T800.descriptor()
+    }
+}
@@ -0,0 +1,269 @@
+/* IEEE 754 arithmetic in all four rounding modes
+
+   The host only rounds to nearest. The other modes are derived from that
+   result by working out which side of it the exact result lies on, using
+   error free transformations, and stepping one ulp where needed. */
+
+use std::cmp::Ordering;
+use std::ops::{Add, Div, Mul, Neg, Sub};
+
+/// Rounding mode of the next floating point operation
+#[derive(Debug, Clone, Copy, PartialEq, Default)]
+pub enum RoundMode{
+    #[default]
+    Nearest,
+    Zero,
+    Plus,
+    Minus
+}
+
+/// What the rounded operations need from f32 and f64
+pub trait Ieee: Copy + PartialOrd + Add<Output = Self> + Sub<Output = Self>
+    + Mul<Output = Self> + Div<Output = Self> + Neg<Output = Self>{
+    const ZERO: Self;
+    /// Largest power of two step that keeps scaling inside the normal range
+    const SCALE_STEP: i32;
+
+    /// self * a + b with a single rounding
+    fn fma(self, a: Self, b: Self) -> Self;
+    fn next_up(self) -> Self;
+    fn next_down(self) -> Self;
+    fn is_finite(self) -> bool;
+    fn is_nan(self) -> bool;
+    fn is_sign_negative(self) -> bool;
+    fn sqrt(self) -> Self;
+    fn round_ties_even(self) -> Self;
+    fn trunc(self) -> Self;
+    fn ceil(self) -> Self;
+    fn floor(self) -> Self;
+    /// 2^e, e must be within the normal exponent range
+    fn pow2(e: i32) -> Self;
+    /// Split a finite non zero value into m * 2^e with 1 <= |m| < 2
+    fn frexp(self) -> (Self, i32);
+}
+
+macro_rules! ieee{
+    ($t:ty, $bits:ty, $mantissa:expr, $exponent:expr, $bias:expr, $step:expr) => {
+        impl Ieee for $t{
+            const ZERO: Self = 0.0;
+            const SCALE_STEP: i32 = $step;
+
+            fn fma(self, a: Self, b: Self) -> Self{ self.mul_add(a, b) }
+            fn next_up(self) -> Self{ <$t>::next_up(self) }
+            fn next_down(self) -> Self{ <$t>::next_down(self) }
+            fn is_finite(self) -> bool{ <$t>::is_finite(self) }
+            fn is_nan(self) -> bool{ <$t>::is_nan(self) }
+            fn is_sign_negative(self) -> bool{ <$t>::is_sign_negative(self) }
+            fn sqrt(self) -> Self{ <$t>::sqrt(self) }
+            fn round_ties_even(self) -> Self{ <$t>::round_ties_even(self) }
+            fn trunc(self) -> Self{ <$t>::trunc(self) }
+            fn ceil(self) -> Self{ <$t>::ceil(self) }
+            fn floor(self) -> Self{ <$t>::floor(self) }
+
+            fn pow2(e: i32) -> Self{
+                <$t>::from_bits(((e + $bias) as $bits) << $mantissa)
+            }
+
+            fn frexp(self) -> (Self, i32){
+                // Subnormals are normalised first
+                let (v, bias) = if self.abs() < <$t>::MIN_POSITIVE{
+                    (self * Self::pow2($mantissa), -$mantissa)
+                }
+                else{
+                    (self, 0)
+                };
+                let bits = v.to_bits();
+                let e = ((bits >> $mantissa) & $exponent) as i32 - $bias;
+                let m = <$t>::from_bits((bits & !($exponent << $mantissa)) | (($bias as $bits) << $mantissa));
+                (m, e + bias)
+            }
+        }
+    };
+}
+
+ieee!(f32, u32, 23, 0xFF, 127, 100);
+ieee!(f64, u64, 52, 0x7FF, 1023, 1000);
+
+/// v * 2^e without intermediate overflow or underflow, as long as the
+/// scaling moves v towards 1
+pub fn ldexp<T: Ieee>(mut v: T, mut e: i32) -> T{
+    while e > T::SCALE_STEP{
+        v = v * T::pow2(T::SCALE_STEP);
+        e -= T::SCALE_STEP;
+    }
+    while e < -T::SCALE_STEP{
+        v = v * T::pow2(-T::SCALE_STEP);
+        e += T::SCALE_STEP;
+    }
+    v * T::pow2(e)
+}
+
+/// Sign of a value as an ordering against zero
+fn sign<T: Ieee>(v: T) -> Ordering{
+    v.partial_cmp(&T::ZERO).unwrap_or(Ordering::Equal)
+}
+
+/// Move the round to nearest result r to the result in mode, given how the
+/// exact result compares with r
+fn adjust<T: Ieee>(r: T, exact: Ordering, mode: RoundMode) -> T{
+    match (mode, exact){
+        (RoundMode::Nearest, _) | (_, Ordering::Equal) => r,
+        (RoundMode::Plus, Ordering::Greater) => r.next_up(),
+        (RoundMode::Minus, Ordering::Less) => r.next_down(),
+        (RoundMode::Zero, Ordering::Greater) if r < T::ZERO => r.next_up(),
+        (RoundMode::Zero, Ordering::Less) if r > T::ZERO => r.next_down(),
+        _ => r
+    }
+}
+
+/// A nearest result that overflowed to infinity lies beyond the exact result
+fn overflowed<T: Ieee>(r: T) -> Ordering{
+    if r > T::ZERO { Ordering::Less } else { Ordering::Greater }
+}
+
+pub fn add<T: Ieee>(b: T, a: T, mode: RoundMode) -> T{
+    let r = b + a;
+    if !(b.is_finite() && a.is_finite()){
+        return r;
+    }
+    if !r.is_finite(){
+        return adjust(r, overflowed(r), mode);
+    }
+    if r == T::ZERO{
+        // An exact zero sum is negative only when rounding down, or when
+        // both operands are negative zero
+        return if mode == RoundMode::Minus && b.is_sign_negative() != a.is_sign_negative(){
+            -T::ZERO
+        }
+        else{
+            r
+        };
+    }
+    // TwoSum, the error is exact
+    let bb = r - a;
+    let err = (b - bb) + (a - (r - bb));
+    adjust(r, sign(err), mode)
+}
+
+pub fn sub<T: Ieee>(b: T, a: T, mode: RoundMode) -> T{
+    if b.is_nan() || a.is_nan(){
+        return b - a;
+    }
+    add(b, -a, mode)
+}
+
+pub fn mul<T: Ieee>(b: T, a: T, mode: RoundMode) -> T{
+    let r = b * a;
+    if !(b.is_finite() && a.is_finite()) || b == T::ZERO || a == T::ZERO{
+        return r;
+    }
+    if !r.is_finite(){
+        return adjust(r, overflowed(r), mode);
+    }
+    // Scale everything near 1 so the error of the product can't underflow
+    let (mb, eb) = b.frexp();
+    let (ma, ea) = a.frexp();
+    let err = mb.fma(ma, -ldexp(r, -(eb + ea)));
+    adjust(r, sign(err), mode)
+}
+
+pub fn div<T: Ieee>(b: T, a: T, mode: RoundMode) -> T{
+    let r = b / a;
+    if !(b.is_finite() && a.is_finite()) || b == T::ZERO || a == T::ZERO{
+        return r;
+    }
+    if !r.is_finite(){
+        return adjust(r, overflowed(r), mode);
+    }
+    // mb - q * ma has the sign of the exact quotient less q when ma is positive
+    let (mb, eb) = b.frexp();
+    let (ma, ea) = a.frexp();
+    let rem = (-ldexp(r, ea - eb)).fma(ma, mb);
+    let exact = if ma < T::ZERO { sign(rem).reverse() } else { sign(rem) };
+    adjust(r, exact, mode)
+}
+
+pub fn sqrt<T: Ieee>(v: T, mode: RoundMode) -> T{
+    let r = v.sqrt();
+    if !v.is_finite() || v <= T::ZERO{
+        return r;
+    }
+    // Scale by an even power of two so the square of the root is exact
+    let (m, e) = v.frexp();
+    let (m, e) = if e % 2 != 0 { (m + m, e - 1) } else { (m, e) };
+    let q = ldexp(r, -e / 2);
+    adjust(r, sign((-q).fma(q, m)), mode)
+}
+
+/// Finish a square root from the approximation g with one more Newton step,
+/// then settle on whichever neighbour of it has the smallest exact residual
+/// and round that. Only correctly rounded when g is already close, as left
+/// by the fpusqrtstep iterations.
+pub fn sqrt_from<T: Ieee>(v: T, g: T, mode: RoundMode) -> T{
+    if !v.is_finite() || v <= T::ZERO{
+        return sqrt(v, mode);
+    }
+    if !g.is_finite() || g <= T::ZERO{
+        return g;
+    }
+    // Scaled by an even power of two, as in sqrt, so residuals are exact
+    let (m, e) = v.frexp();
+    let (m, e) = if e % 2 != 0 { (m + m, e - 1) } else { (m, e) };
+    let g = ldexp(g, -e / 2);
+    let q = (g + m / g) * T::pow2(-1);
+    let residual = |c: T| (-c).fma(c, m);
+    let magnitude = |c: T| { let r = residual(c); if r < T::ZERO { -r } else { r } };
+    let mut best = q;
+    for c in [q.next_down(), q.next_up()]{
+        if magnitude(c) < magnitude(best){
+            best = c;
+        }
+    }
+    adjust(ldexp(best, e / 2), sign(residual(best)), mode)
+}
+
+/// IEEE remainder, v - n * d where n is v / d rounded to the nearest
+/// integer, ties to even. Always exact.
+pub fn remainder(v: f64, d: f64) -> f64{
+    if v.is_nan() || d.is_nan() || v.is_infinite() || d == 0.0{
+        return f64::NAN;
+    }
+    if d.is_infinite(){
+        return v;
+    }
+    let d = d.abs();
+    // Reduce below 2d first so the parity of n is known, fmod is exact
+    let mut r = if d <= f64::MAX / 2.0 { (v % (d + d)).abs() } else { v.abs() };
+    if r + r > d{
+        r -= d;
+        if r + r >= d{
+            r -= d;
+        }
+    }
+    if v.is_sign_negative() { -r } else { r }
+}
+
+/// Round to an integer valued real
+pub fn int<T: Ieee>(v: T, mode: RoundMode) -> T{
+    match mode{
+        RoundMode::Nearest => v.round_ties_even(),
+        RoundMode::Zero => v.trunc(),
+        RoundMode::Plus => v.ceil(),
+        RoundMode::Minus => v.floor()
+    }
+}
+
+/// Double to single length
+pub fn narrow(v: f64, mode: RoundMode) -> f32{
+    let r = v as f32;
+    if !v.is_finite(){
+        return r;
+    }
+    adjust(r, v.partial_cmp(&(r as f64)).unwrap_or(Ordering::Equal), mode)
+}
+
+/// Integer to single length, doubles hold every INT32 exactly
+pub fn int_to_single(v: i32, mode: RoundMode) -> f32{
+    let r = v as f32;
+    adjust(r, (v as f64).partial_cmp(&(r as f64)).unwrap_or(Ordering::Equal), mode)
+}
@@ -1,12 +1,31 @@
 /* Define secondary functions */
 
-use super::{NOT_PROCESS_P, OpErr, OpVal, Priority, ProcLibrary};
-use crate::{mem::*, proc::GO_TO_SNP_BIT};
+use super::{IPTR_S, NOT_PROCESS_P, Move2d, OpErr, OpResult, OpVal, Priority, Proc, ProcLibrary};
+use super::{is_after, ENABLING_P, NONE_SELECTED_O, READY_P, STATE_S, TIME_NOT_SET_P, TIME_S, TIME_SET_P, TLINK_S, WAITING_P};
+use crate::proc::GO_TO_SNP_BIT;
+use crate::checksum::{crc_byte, crc_word};
+
+/// Copy the 2D block set up by move2dinit, A is the width in bytes,
+/// B the destination and C the source address
+fn move2d(p: &mut Proc, keep: fn(u8) -> bool) -> OpResult{
+    let width = p.stack.pop();
+    let dest = p.stack.pop();
+    let source = p.stack.pop();
+    let Move2d{ source_stride, dest_stride, length } = p.move2d;
+    if width <= 0{
+        return Ok(OpVal::Null);
+    }
+    for row in 0..length.max(0){
+        p.mem.copy_if(source.wrapping_add(row.wrapping_mul(source_stride)),
+            dest.wrapping_add(row.wrapping_mul(dest_stride)), width as u32, keep)?;
+    }
+    Ok(OpVal::Null)
+}
 
 /// Instructions encoded without using prefix
 pub fn define_wo_prefix(pl: &mut ProcLibrary){
     // Reverse top of stack
-    pl.define_indirect("rev",0x0, |p|{
+    pl.define_indirect("rev", |p|{
         let a = p.stack.pop();
         let b = p.stack.pop();
         p.stack.push(a);
@@ -15,51 +34,59 @@ pub fn define_wo_prefix(pl: &mut ProcLibrary){
     });
     
     // Load byte
-    pl.define_indirect("lb", 0x1, |p|{
+    pl.define_indirect("lb", |p|{
         let a = p.stack.pop();
-        let v = p.mem.read_byte(a);
+        let v = p.mem.read_byte(a)?;
         Ok(OpVal::Int(v as i32))
     });
     
     // Bsub byte subscript
-    pl.define_indirect("bsub", 0x2, |p|{
+    pl.define_indirect("bsub", |p|{
         let a = p.stack.pop();
         let b = p.stack.pop();
         Ok(OpVal::Int(a.wrapping_add(b)))
     });
     
     // End process
-    pl.define_indirect("endp", 0x3, |p|{
-        // TODO check documentation for endp
+    pl.define_indirect("endp", |p|{
+        // A is the parent's workspace, holding the Iptr to continue at
+        // then the number of processes still running
         let a = p.stack.pop();
-        let flag = p.mem.read(a + 4);
+        let flag = p.mem.read(a.wrapping_add(4))?;
         if flag == 1{
-            p.pc = p.mem.read(a);
-            p.workspace = a;
+            // Last process to finish continues the parent
+            p.pc = p.mem.read(a)?;
+            p.update_wdesc(a | (p.descriptor & 0b1));
         }
         else{
-            p.mem.write(a+4, flag-1);
-            p.status = p.status | GO_TO_SNP_BIT;
+            p.mem.write(a.wrapping_add(4), flag.wrapping_sub(1))?;
+            p.status |= GO_TO_SNP_BIT;
         }
         Ok(OpVal::Null)
     });
     
-    // 0x4 Difference
+    // 0x4 Difference, no overflow check
+    pl.define_indirect("diff", |p|{
+        let a = p.stack.pop();
+        let b = p.stack.pop();
+        Ok(OpVal::Int(b.wrapping_sub(a)))
+    });
     
     // 0x5 Add
-    pl.define_indirect("add", 0x5, |p|{
+    pl.define_indirect("add", |p|{
         let a = p.stack.pop();
         let b = p.stack.pop();
         
-        match a.checked_add(b){
-            Some(v) => p.stack.push(v),
-            None => p.throw_error(OpErr::Overflow)
+        // Result wraps on overflow and sets the error flag
+        p.stack.push(a.wrapping_add(b));
+        if a.checked_add(b).is_none(){
+            p.throw_error(OpErr::Overflow);
         }
         Ok(OpVal::Null)
     });
     
     // 0x6 General call
-    pl.define_indirect("gcall", 0x6, |p|{
+    pl.define_indirect("gcall", |p|{
         let a = p.stack.pop();
         let t = p.pc;
         p.pc = a;
@@ -67,16 +94,23 @@ pub fn define_wo_prefix(pl: &mut ProcLibrary){
     });
     
     // 0x7 Input message
+    pl.define_indirect("in", |p|{
+        let a = p.stack.pop();
+        let b = p.stack.pop();
+        let c = p.stack.pop();
+        p.communicate(b, c, a, true)?;
+        Ok(OpVal::Null)
+    });
     
     // 0x8 Product
-    pl.define_indirect("prod", 0x8, |p|{
+    pl.define_indirect("prod", |p|{
         let a = p.stack.pop();
         let b = p.stack.pop();
-        Ok(OpVal::Int(a * b))
+        Ok(OpVal::Int(a.wrapping_mul(b)))
     });
     
     // 0x9 Greather than
-    pl.define_indirect("gt", 0x9, |p|{
+    pl.define_indirect("gt", |p|{
         let a = p.stack.pop();
         let b = p.stack.pop();
         if b > a{
@@ -89,181 +123,214 @@ pub fn define_wo_prefix(pl: &mut ProcLibrary){
     
     // 0xA Word subscript
     // Line 1344 of nanochess transputer_emulator.js transputer has a order of operatations 
-    pl.define_indirect("wsub", 0xA, |p|{
+    pl.define_indirect("wsub", |p|{
         let a = p.stack.pop();
         let b = p.stack.pop() << 2;
-        Ok(OpVal::Int(a + b))
+        Ok(OpVal::Int(a.wrapping_add(b)))
     });
     
     // 0xB Output message
+    pl.define_indirect("out", |p|{
+        let a = p.stack.pop();
+        let b = p.stack.pop();
+        let c = p.stack.pop();
+        p.communicate(b, c, a, false)?;
+        Ok(OpVal::Null)
+    });
     
     // 0xD Start process
     // startp
-    pl.define_indirect("startp", 0x0D, |p|{
+    pl.define_indirect("startp", |p|{
         let a = p.stack.pop();
         let b = p.stack.pop();
         // Start new process at a
-        p.mem.write(a - 4, p.pc + b);
+        p.mem.write(a.wrapping_add(IPTR_S), p.pc.wrapping_add(b))?;
         
         // Run with same priority
-        p.run_process(a | (p.descriptor & 0b1));
+        p.run_process(a | (p.descriptor & 0b1))?;
         Ok(OpVal::Null)
     });
     
     // 0xF Output word
+    // The word is sent from Wptr[0]
+    pl.define_indirect("outword", |p|{
+        let a = p.stack.pop();
+        let b = p.stack.pop();
+        p.mem.write(p.workspace, a)?;
+        p.communicate(b, p.workspace, 4, false)?;
+        Ok(OpVal::Null)
+    });
     
     // 0xE Output byte
+    pl.define_indirect("outbyte", |p|{
+        let a = p.stack.pop();
+        let b = p.stack.pop();
+        p.mem.write(p.workspace, a & 0xFF)?;
+        p.communicate(b, p.workspace, 1, false)?;
+        Ok(OpVal::Null)
+    });
     
     // 0xC Subtract
-    pl.define_indirect("sub", 0xC, |p|{
+    pl.define_indirect("sub", |p|{
         let a = p.stack.pop();
         let b = p.stack.pop();
-        Ok(OpVal::Int(b - a))
+        p.stack.push(b.wrapping_sub(a));
+        if b.checked_sub(a).is_none(){
+            p.throw_error(OpErr::Overflow);
+        }
+        Ok(OpVal::Null)
     });
 }
 
 pub fn define_w_prefix(pl: &mut ProcLibrary){
     // tin
-    pl.define_indirect("tin", 0x2B, |p|{
-        let temp =match p.priority(){
-            Priority::Low => p.mem.read(CLOCK_REG_0),
-            Priority::High => p.mem.read(CLOCK_REG_1)
-        };
+    // Waits until the clock has reached A
+    pl.define_indirect("tin", |p|{
         let a = p.stack.pop();
-        if a - temp > 0{
-            // Should wait
-            // Save workspace state
-            p.mem.write(p.workspace - 4, p.pc);
-            p.mem.write(p.workspace - 20, a); // Time for awakening
-            // Get addresses of timers
-            todo!("Link timer");
-            p.status = p.status | GO_TO_SNP_BIT;
+        if is_after(a, p.get_clock_register(p.priority())){
+            p.timer_wait(a)?;
         }
         Ok(OpVal::Null)
     });
     
     // stop process
-    pl.define_indirect("stopp", 0x15, |p|{
-        p.mem.write(p.workspace - 4, p.pc);
-        p.status = p.status | GO_TO_SNP_BIT;
+    pl.define_indirect("stopp", |p|{
+        p.wait()?;
         Ok(OpVal::Null)
     });
     
     // Run p
-    pl.define_indirect("runp", 0x39, |p|{
+    pl.define_indirect("runp", |p|{
         let a = p.stack.pop();
-        p.run_process(a);
+        p.run_process(a)?;
         Ok(OpVal::Null)
     });
     
     // Save Low priority info
-    pl.define_indirect("savel", 0x3D, |p|{
+    pl.define_indirect("savel", |p|{
         let a = p.stack.pop();
-        p.mem.write(a, p.get_front_pointer(Priority::Low));
-        p.mem.write(a+4, p.get_back_pointer(Priority::Low));
+        p.mem.write(a, p.get_front_pointer(Priority::Low))?;
+        p.mem.write(a.wrapping_add(4), p.get_back_pointer(Priority::Low))?;
         Ok(OpVal::Null)
     });
     
     // Save high priority info
-    pl.define_indirect("saveh",0x3E, |p|{
+    pl.define_indirect("saveh", |p|{
         let a = p.stack.pop();
-        p.mem.write(a, p.get_front_pointer(Priority::High));
-        p.mem.write(a+4, p.get_back_pointer(Priority::High));
+        p.mem.write(a, p.get_front_pointer(Priority::High))?;
+        p.mem.write(a.wrapping_add(4), p.get_back_pointer(Priority::High))?;
         Ok(OpVal::Null)
     });
     
     // Set front and back process pointers
     // STHB
-    pl.define_indirect("sthb", 0x50, |p|{
+    pl.define_indirect("sthb", |p|{
         let a = p.stack.pop();
         p.set_back_pointer(Priority::High, a);
         Ok(OpVal::Null)
     });
     
     // Sthl
-    pl.define_indirect("stlb", 0x17, |p|{
+    pl.define_indirect("stlb", |p|{
         let a = p.stack.pop();
         p.set_back_pointer(Priority::Low, a);
         Ok(OpVal::Null)
     });
     
     // STHB
-    pl.define_indirect("sthf", 0x18, |p|{
+    pl.define_indirect("sthf", |p|{
         let a = p.stack.pop();
         p.set_front_pointer(Priority::High, a);
         Ok(OpVal::Null)
     });
     
     // Sthl
-    pl.define_indirect("stlf", 0x1C, |p|{
+    pl.define_indirect("stlf", |p|{
         let a = p.stack.pop();
         p.set_front_pointer(Priority::Low, a);
         Ok(OpVal::Null)
     });
     
     // Reset channel
-    pl.define_indirect("resetch", 0x12, |p|{
+    pl.define_indirect("resetch", |p|{
         let a = p.stack.pop();
-        let v = p.mem.read(a);
-        p.mem.write(a, NOT_PROCESS_P);
+        let v = p.mem.read(a)?;
+        p.mem.write(a, NOT_PROCESS_P)?;
         Ok(OpVal::Int(v))
     });
     
     // Set error
-    pl.define_indirect("seterr", 0x10, |p|{
-        p.error = 1;
+    pl.define_indirect("seterr", |p|{
+        p.throw_error(OpErr::Err);
         Ok(OpVal::Null)
     });
     
     // Csub0
-    pl.define_indirect("csub0",0x13, |p|{
+    pl.define_indirect("csub0", |p|{
         let a = p.stack.pop();
         let b = p.stack.pop();
-        if b >= a{
-            p.error = 1;
+        p.stack.push(b);
+        if (b as u32) >= (a as u32){
+            p.throw_error(OpErr::Err);
         }
-        Ok(OpVal::Int(b))
+        Ok(OpVal::Null)
     });
     
-    // ladd
-    pl.define_indirect("ladd", 0x16, |p|{
+    // Long add, signed with carry in from C
+    pl.define_indirect("ladd", |p|{
         let a = p.stack.pop();
         let b = p.stack.pop();
         let c = p.stack.pop();
-        match b.checked_add(a + (c & 0b1)){
-            Some(v) => Ok(OpVal::Int(v)),
-            None => Err(OpErr::Overflow)   
+        let result = b as i64 + a as i64 + (c & 0b1) as i64;
+        p.stack.push(result as i32);
+        if result != result as i32 as i64{
+            p.throw_error(OpErr::Overflow);
         }
+        Ok(OpVal::Null)
     });
     
     // norm
-    // normalizes the double value of A
-    pl.define_indirect("norm",0x19, |p|{
-        let mut c = 0;
-        let mut a = p.stack.pop();
-        let mut b = p.stack.pop();
-        while c < 64 && ((b & 0x8000_0000u32 as i32) == 0){
-            b = (b << 1) | (a >> 31);
-            a = a << 1;
-            c += 1;
+    // Shift the double length value B:A left until the top bit of B is set,
+    // C is the number of places shifted, 64 for zero
+    pl.define_indirect("norm", |p|{
+        let a = p.stack.pop() as u32 as u64;
+        let b = p.stack.pop() as u32 as u64;
+        let value = (b << 32) | a;
+        let count = value.leading_zeros();
+        let value = value.checked_shl(count).unwrap_or(0);
+        Ok(OpVal::List(vec![count as i32, (value >> 32) as i32, value as i32]))
+    });
+    
+    // Long divide, unsigned C:B by A, A is the quotient and B the remainder
+    pl.define_indirect("ldiv", |p|{
+        let a = p.stack.pop() as u32 as u64;
+        let b = p.stack.pop() as u32 as u64;
+        let c = p.stack.pop() as u32 as u64;
+        // The quotient must fit in a word
+        if c >= a{
+            p.stack.push(0);
+            p.stack.push(0);
+            p.throw_error(OpErr::Overflow);
+            return Ok(OpVal::Null);
         }
-        Ok(OpVal::List(vec![a, b]))
+        let value = (c << 32) | b;
+        Ok(OpVal::List(vec![(value % a) as i32, (value / a) as i32]))
     });
     
     // ldpi
-    pl.define_indirect("ldpi", 0x1B, |p|{
-        let _a = p.stack.set(0, p.stack.a() + p.pc);
+    pl.define_indirect("ldpi", |p|{
+        p.stack.set(0, p.stack.a().wrapping_add(p.pc));
         Ok(OpVal::Null)
     });
     
     // xdble
-    pl.define_indirect("xdble", 0x1D, |p|{
+    pl.define_indirect("xdble", |p|{
         let a = p.stack.pop();
         Ok(OpVal::List(vec![a >> 31, a]))
     });
     
     // ldri - load priority
-    pl.define_indirect("ldpri", 0x1E, |p|{
+    pl.define_indirect("ldpri", |p|{
         Ok(OpVal::Int(match p.priority(){
             Priority::Low => 1,
             Priority::High => 0
@@ -271,223 +338,325 @@ pub fn define_w_prefix(pl: &mut ProcLibrary){
     });
     
     // remainde
-    pl.define_indirect("rem", 0x1F, |p|{
+    pl.define_indirect("rem", |p|{
         let a = p.stack.pop();
         let b = p.stack.pop();
         
-        if a == 0 || a == -0{
+        if a == 0{
             return Err(OpErr::DivideByZero);
         }
         
-        Ok(OpVal::Int(b % a))
+        Ok(OpVal::Int(b.wrapping_rem(a)))
     });
     
     // return
-    pl.define_indirect("ret", 0x20, |p|{
-        p.pc = p.mem.read(p.workspace);
-        p.workspace += 16;
-        p.update_wdesc(p.workspace | p.priority() as i32);
+    pl.define_indirect("ret", |p|{
+        p.pc = p.mem.read(p.workspace)?;
+        p.update_wdesc(p.workspace.wrapping_add(16) | p.priority() as i32);
         Ok(OpVal::Null)
     });
     
     // lend
     // Loop end
-    pl.define_indirect("lend", 0x21, |p|{
-        let a = p.stack.a();
-        let b = p.stack.b() & (!0b11);
+    pl.define_indirect("lend", |p|{
+        // A is the offset back to the loop start, B points to the
+        // control block holding the index then the remaining count
+        let a = p.stack.pop();
+        let b = p.stack.pop() & (!0b11);
         
-        // Decrement by 1
-        let wp = p.mem.read(b+4) - 1;
-        p.mem.write(b+4, wp);
-        if wp == 0{
+        // Decrement count by 1
+        let count = p.mem.read(b.wrapping_add(4))?.wrapping_sub(1);
+        p.mem.write(b.wrapping_add(4), count)?;
+        if count <= 0{
             return Ok(OpVal::Null)
         }
         
-        p.mem.write(b, wp);
-        p.pc = p.pc - a;
-        if p.priority() == Priority::Low{
-            // Deschedule
-            p.mem.write(p.workspace - 4, p.pc);
-            if p.get_front_pointer(Priority::Low) == NOT_PROCESS_P{
-                p.set_front_pointer(Priority::High, p.workspace);
-            }
-            else{
-                p.mem.write(p.get_back_pointer(Priority::Low) - 8, p.workspace);
-            }
-            p.set_back_pointer(Priority::Low, p.workspace);
-            p.status |= GO_TO_SNP_BIT;
-        }
+        p.mem.write(b, p.mem.read(b)?.wrapping_add(1))?;
+        p.pc = p.pc.wrapping_sub(a);
+        p.timeslice()?;
         Ok(OpVal::Null)
     });
     
     // Load timer
-    pl.define_indirect("ldtimer", 0x22, |p|{
+    pl.define_indirect("ldtimer", |p|{
         Ok(OpVal::Int(p.get_clock_register(p.priority())))
     });
     
-    // Test err
-    pl.define_indirect("testerr", 0x29, |p|{
-        let err = p.error;
-        p.error = 0;
-        Ok(OpVal::Int(err as i32))
+    // Test error false and clear
+    pl.define_indirect("testerr", |p|{
+        let err = p.error_flag();
+        p.clear_error();
+        Ok(OpVal::Int(if err { 0 } else { 1 }))
     });
     
-    // Test analysis pin
-    pl.define_indirect("testpranal", 0x2A, |_p|{
-        println!("Analysis pin non existent");
+    // Test analysis pin, there is no pin so it is never asserted
+    pl.define_indirect("testpranal", |_p|{
         Ok(OpVal::Int(0))
     });
     
     // Division
-    pl.define_indirect("div", 0x2C, |p|{
+    pl.define_indirect("div", |p|{
         let a = p.stack.pop();
         let b = p.stack.pop();
-        if a == 0{
-            return Err(OpErr::DivideByZero)
+        match b.checked_div(a){
+            Some(v) => Ok(OpVal::Int(v)),
+            None => Err(OpErr::DivideByZero)
+        }
+    });
+    
+    // Disable timer
+    // Selects the guard if it is the first ready one and the time has been reached
+    pl.define_indirect("dist", |p|{
+        let a = p.stack.pop();
+        let b = p.stack.pop();
+        let c = p.stack.pop();
+        p.remove_timer(p.priority(), p.workspace)?;
+        let now = p.get_clock_register(p.priority());
+        if b == 0 || is_after(c, now){
+            return Ok(OpVal::Int(0))
         }
-        Ok(OpVal::Int(b / a))
+        Ok(OpVal::Int(p.select_guard(a)? as i32))
     });
     
-    // Dist
-    pl.define_indirect("dist", 0x2E, |_p|{
-        todo!("Dist not implemented");
+    // Disable channel
+    pl.define_indirect("disc", |p|{
+        let a = p.stack.pop();
+        let b = p.stack.pop();
+        let c = p.stack.pop();
+        if b == 0{
+            return Ok(OpVal::Int(0))
+        }
+        let v = p.mem.read(c)?;
+        if v == p.descriptor{
+            // Nobody arrived, remove ourselves from the channel
+            p.mem.write(c, NOT_PROCESS_P)?;
+            return Ok(OpVal::Int(0))
+        }
+        if v == NOT_PROCESS_P{
+            return Ok(OpVal::Int(0))
+        }
+        Ok(OpVal::Int(p.select_guard(a)? as i32))
     });
     
-    pl.define_indirect("diss", 0x30, |_p|{
-        todo!("Diss not implemented");
+    // Disable skip
+    pl.define_indirect("diss", |p|{
+        let a = p.stack.pop();
+        let b = p.stack.pop();
+        if b == 0{
+            return Ok(OpVal::Int(0))
+        }
+        Ok(OpVal::Int(p.select_guard(a)? as i32))
     });
     
     // bitwise not
-    pl.define_indirect("not", 0x32, |p|{
+    pl.define_indirect("not", |p|{
         let a = p.stack.pop();
         Ok(OpVal::Int(!a))
     });
     
     // xor
-    pl.define_indirect("xor",0x33, |p|{
+    pl.define_indirect("xor", |p|{
         let a = p.stack.pop();
         let b = p.stack.pop();
         Ok(OpVal::Int(b ^ a))
     });
     
     // Bit count?
-    pl.define_indirect("bcnt", 0x34, |p|{
+    pl.define_indirect("bcnt", |p|{
         let a = p.stack.pop();
         Ok(OpVal::Int(a << 2))
     });
     
-    // long subtraction
-    pl.define_indirect("lsub", 0x38, |p|{
+    // Long multiply, unsigned B * A + C, A is the low word and B the high word
+    pl.define_indirect("lmul", |p|{
+        let a = p.stack.pop() as u32 as u64;
+        let b = p.stack.pop() as u32 as u64;
+        let c = p.stack.pop() as u32 as u64;
+        let result = b * a + c;
+        Ok(OpVal::List(vec![(result >> 32) as i32, result as i32]))
+    });
+    
+    // Long shift right, C:B by A places, A is the low word and B the high word
+    pl.define_indirect("lshr", |p|{
+        let a = p.stack.pop() as u32;
+        let b = p.stack.pop() as u32 as u64;
+        let c = p.stack.pop() as u32 as u64;
+        let result = ((c << 32) | b).checked_shr(a).unwrap_or(0);
+        Ok(OpVal::List(vec![(result >> 32) as i32, result as i32]))
+    });
+    
+    // Long shift left, C:B by A places
+    pl.define_indirect("lshl", |p|{
+        let a = p.stack.pop() as u32;
+        let b = p.stack.pop() as u32 as u64;
+        let c = p.stack.pop() as u32 as u64;
+        let result = ((c << 32) | b).checked_shl(a).unwrap_or(0);
+        Ok(OpVal::List(vec![(result >> 32) as i32, result as i32]))
+    });
+    
+    // Long sum, unsigned with carry in from C, the carry out goes to B
+    pl.define_indirect("lsum", |p|{
+        let a = p.stack.pop() as u32 as u64;
+        let b = p.stack.pop() as u32 as u64;
+        let c = p.stack.pop() as u32 as u64;
+        let result = b + a + (c & 0b1);
+        Ok(OpVal::List(vec![(result >> 32) as i32, result as i32]))
+    });
+    
+    // Long subtract, signed with borrow in from C
+    pl.define_indirect("lsub", |p|{
         let a = p.stack.pop();
         let b = p.stack.pop();
         let c = p.stack.pop();
-        let result = b.checked_sub(a - (c & 0b1));
-        match result{
-            Some(v) => Ok(OpVal::Int(v)),
-            None => Err(OpErr::Overflow)
+        let result = b as i64 - a as i64 - (c & 0b1) as i64;
+        p.stack.push(result as i32);
+        if result != result as i32 as i64{
+            p.throw_error(OpErr::Overflow);
         }
+        Ok(OpVal::Null)
     });
     
     // xword
-    pl.define_indirect("xword", 0x3A, |p|{
+    pl.define_indirect("xword", |p|{
         let a = p.stack.pop();
         let b = p.stack.pop();
         if b < a{
             Ok(OpVal::Int(b))
         }
         else{
-            Ok(OpVal::Int((b - a) << 1))
+            Ok(OpVal::Int(b.wrapping_sub(a << 1)))
         }
     });
     
     // Store byte
-    pl.define_indirect("sb", 0x3B, |p|{
+    pl.define_indirect("sb", |p|{
         let a = p.stack.pop();
         let b = p.stack.pop() & 0xFF;
-        p.mem.write_byte(a, b as u8);
+        p.mem.write_byte(a, b as u8)?;
         Ok(OpVal::Null)
     });
     
     // gajw
-    pl.define_indirect("gajw", 0x3C, |p|{
-        p.workspace = p.stack.a() & (!0b11);
-        p.update_wdesc(p.workspace | p.priority() as i32);
-        Ok(OpVal::Null)
+    // Swap A and the workspace pointer
+    pl.define_indirect("gajw", |p|{
+        let a = p.stack.pop();
+        let workspace = p.workspace;
+        p.update_wdesc((a & !0b11) | p.priority() as i32);
+        Ok(OpVal::Int(workspace))
     });
     
     // wcnt
-    pl.define_indirect("wcnt", 0x3F, |p|{
+    pl.define_indirect("wcnt", |p|{
         let a = p.stack.pop();
         Ok(OpVal::List(vec![a & 0b11, a >> 2]))
     });
     
     // shr
-    pl.define_indirect("shr", 0x40, |p|{
+    pl.define_indirect("shr", |p|{
         let a = p.stack.pop();
         let b = p.stack.pop();
-        Ok(OpVal::Int(b >> a))
+        // Logical shift, counts of a word or more clear the value
+        Ok(OpVal::Int((b as u32).checked_shr(a as u32).unwrap_or(0) as i32))
     });
     
     // shl
-    pl.define_indirect("shl", 0x40, |p|{
+    pl.define_indirect("shl", |p|{
         let a = p.stack.pop();
         let b = p.stack.pop();
-        Ok(OpVal::Int(a << b))
+        Ok(OpVal::Int((b as u32).checked_shl(a as u32).unwrap_or(0) as i32))
     });
     
     // minimum integer
-    pl.define_indirect("mint", 0x42, |_p|{
+    pl.define_indirect("mint", |_p|{
         Ok(OpVal::Int(0x8000_0000u32 as i32))
     });
     
-    // alt commands are not implemented for now
-    pl.define_indirect("alt", 0x42, |_p|{
-        todo!("alt command not implemented");
+    // Alt start
+    pl.define_indirect("alt", |p|{
+        p.mem.write(p.workspace.wrapping_add(STATE_S), ENABLING_P)?;
+        Ok(OpVal::Null)
     });
     
-    pl.define_indirect("altwt", 0x44, |_p|{
-        todo!("altwt command not implemented");
+    // Alt wait, deschedules until a guard is ready
+    pl.define_indirect("altwt", |p|{
+        p.mem.write(p.workspace, NONE_SELECTED_O)?;
+        if p.mem.read(p.workspace.wrapping_add(STATE_S))? != READY_P{
+            p.mem.write(p.workspace.wrapping_add(STATE_S), WAITING_P)?;
+            p.wait()?;
+        }
+        Ok(OpVal::Null)
     });
     
-    pl.define_indirect("altend", 0x45, |_p|{
-        todo!("altend command not implemented");
+    // Alt end, jump to the selected guard
+    pl.define_indirect("altend", |p|{
+        p.pc = p.pc.wrapping_add(p.mem.read(p.workspace)?);
+        Ok(OpVal::Null)
     });
     
-    pl.define_indirect("and", 0x46, |p|{
+    pl.define_indirect("and", |p|{
         let a = p.stack.pop();
         let b = p.stack.pop();
         Ok(OpVal::Int(a & b))
     });
     
-    pl.define_indirect("enbt", 0x47, |_p|{
-        todo!("enbt not implemented");
+    // Enable timer, keeps the earliest guard time in Time.s
+    pl.define_indirect("enbt", |p|{
+        let a = p.stack.pop();
+        let b = p.stack.pop();
+        if a != 0{
+            let tlink = p.workspace.wrapping_add(TLINK_S);
+            let time = p.workspace.wrapping_add(TIME_S);
+            if p.mem.read(tlink)? == TIME_NOT_SET_P{
+                p.mem.write(tlink, TIME_SET_P)?;
+                p.mem.write(time, b)?;
+            }
+            else if is_after(p.mem.read(time)?, b){
+                p.mem.write(time, b)?;
+            }
+        }
+        Ok(OpVal::Int(a))
     });
     
-    pl.define_indirect("enbc", 0x48, |_p|{
-        todo!("enbc not implemented");
+    // Enable channel
+    pl.define_indirect("enbc", |p|{
+        let a = p.stack.pop();
+        let b = p.stack.pop();
+        if a != 0{
+            let v = p.mem.read(b)?;
+            if v == NOT_PROCESS_P{
+                p.mem.write(b, p.descriptor)?;
+            }
+            else if v != p.descriptor{
+                // Another process is waiting to output
+                p.mem.write(p.workspace.wrapping_add(STATE_S), READY_P)?;
+            }
+        }
+        Ok(OpVal::Int(a))
     });
     
-    pl.define_indirect("enbs", 0x48, |_p|{
-        todo!("enbs not implemented");
+    // Enable skip
+    pl.define_indirect("enbs", |p|{
+        let a = p.stack.a();
+        if a != 0{
+            p.mem.write(p.workspace.wrapping_add(STATE_S), READY_P)?;
+        }
+        Ok(OpVal::Null)
     });
     
     // Move array in memory
-    pl.define_indirect("move", 0x4A, |p|{
+    pl.define_indirect("move", |p|{
         // Move n bytes from one location to the next
-        let mut a = p.stack.pop();
-        let mut b = p.stack.pop();
-        let mut c = p.stack.pop();
-        while a > 0{
-            p.mem.write_byte(b, p.mem.read_byte(c));
-            c += 1;
-            b += 1;
-            a -= 1;
+        let a = p.stack.pop();
+        let b = p.stack.pop();
+        let c = p.stack.pop();
+        if a > 0{
+            p.mem.copy(c, b, a as u32)?;
         }
         Ok(OpVal::Null)
     });
     
     // Or arithmetic
-    pl.define_indirect("or", 0x4B, |p|{
+    pl.define_indirect("or", |p|{
         let a = p.stack.pop();
         let b = p.stack.pop();
         Ok(OpVal::Int(a | b))
@@ -495,7 +664,7 @@ pub fn define_w_prefix(pl: &mut ProcLibrary){
     
     // csngl
     // Check if double value can be reduced to single
-    pl.define_indirect("csngl", 0x4C, |p|{
+    pl.define_indirect("csngl", |p|{
         let a = p.stack.pop();
         let b = p.stack.pop();
         if a < 0 && b != -1{
@@ -508,42 +677,320 @@ pub fn define_w_prefix(pl: &mut ProcLibrary){
     });
     
     // ccnt1
-    pl.define_indirect("ccnt1", 0x4D, |p|{
+    pl.define_indirect("ccnt1", |p|{
         let a = p.stack.pop();
         let b = p.stack.pop();
         
         if b == 0 || b > a{
             return Err(OpErr::Count);
         }
-        return Ok(OpVal::Int(b))
+        Ok(OpVal::Int(b))
+    });
+    
+    // Timer alt start
+    pl.define_indirect("talt", |p|{
+        p.mem.write(p.workspace.wrapping_add(STATE_S), ENABLING_P)?;
+        p.mem.write(p.workspace.wrapping_add(TLINK_S), TIME_NOT_SET_P)?;
+        Ok(OpVal::Null)
     });
     
-    // talt
-    pl.define_indirect("talt", 0x4E, |_p|{
-        todo!("talt not defined");
+    // Long difference, unsigned with borrow in from C, the borrow out goes to B
+    pl.define_indirect("ldiff", |p|{
+        let a = p.stack.pop() as u32 as u64;
+        let b = p.stack.pop() as u32 as u64;
+        let c = p.stack.pop() as u32 as u64;
+        let result = b.wrapping_sub(a).wrapping_sub(c & 0b1);
+        Ok(OpVal::List(vec![(result >> 63) as i32, result as i32]))
+    });
+    
+    // Timer alt wait
+    pl.define_indirect("taltwt", |p|{
+        p.mem.write(p.workspace, NONE_SELECTED_O)?;
+        let state = p.workspace.wrapping_add(STATE_S);
+        if p.mem.read(state)? != READY_P{
+            if p.mem.read(p.workspace.wrapping_add(TLINK_S))? == TIME_SET_P{
+                let now = p.get_clock_register(p.priority());
+                let time = p.mem.read(p.workspace.wrapping_add(TIME_S))?;
+                if is_after(time, now){
+                    // Wait for a channel or the earliest guard time
+                    p.timer_wait(time)?;
+                }
+                else{
+                    p.mem.write(state, READY_P)?;
+                }
+            }
+            else{
+                p.mem.write(state, WAITING_P)?;
+                p.wait()?;
+            }
+        }
+        Ok(OpVal::Null)
     });
     
-    // taltwt
-    pl.define_indirect("taltwt", 0x51, |_p|{
-        todo!("taltwt not defined");
+    // Set both clocks
+    pl.define_indirect("sttimer", |p|{
+        let a = p.stack.pop();
+        p.set_clock_registers(a);
+        Ok(OpVal::Null)
     });
     
     // sum
-    pl.define_indirect("sum", 0x52, |p|{
+    pl.define_indirect("sum", |p|{
         let a = p.stack.pop();
         let b = p.stack.pop();
-        Ok(OpVal::Int(a + b))
+        Ok(OpVal::Int(a.wrapping_add(b)))
     });
     
     // mul
-    pl.define_indirect("mul", 0x53, |p|{
+    pl.define_indirect("mul", |p|{
         let a = p.stack.pop();
         let b = p.stack.pop();
-        Ok(OpVal::Int(a * b))
+        p.stack.push(a.wrapping_mul(b));
+        if a.checked_mul(b).is_none(){
+            p.throw_error(OpErr::Overflow);
+        }
+        Ok(OpVal::Null)
     });
     
+    // Stop process if error flag is set
+    pl.define_indirect("stoperr", |p|{
+        if p.error_flag(){
+            p.mem.write(p.workspace.wrapping_add(IPTR_S), p.pc)?;
+            p.status |= GO_TO_SNP_BIT;
+        }
+        Ok(OpVal::Null)
+    });
+    
+    // Check that B fits in a word part whose top bit is A, A is set to B
+    pl.define_indirect("cword", |p|{
+        let a = p.stack.pop();
+        let b = p.stack.pop();
+        if b < a.wrapping_neg() || b >= a{
+            p.throw_error(OpErr::Err);
+        }
+        Ok(OpVal::Int(b))
+    });
+    
+    // Clear halt on error
+    pl.define_indirect("clrhalterr", |p|{
+        p.set_halt_on_error(false);
+        Ok(OpVal::Null)
+    });
+    
+    // Set halt on error
+    pl.define_indirect("sethalterr", |p|{
+        p.set_halt_on_error(true);
+        Ok(OpVal::Null)
+    });
+    
+    // Test halt on error
+    pl.define_indirect("testhalterr", |p|{
+        Ok(OpVal::Int(p.halt_on_error() as i32))
+    });
+}
+
+/// Instructions added with the T425 and T805
+pub fn define_extended(pl: &mut ProcLibrary){
     // dup
-    pl.define_indirect("dup", 0x5A, |p|{
+    pl.define_indirect("dup", |p|{
         Ok(OpVal::Int(p.stack.a()))
     });
-}
\ No newline at end of file
+    
+    // Drop A
+    pl.define_indirect("pop", |p|{
+        p.stack.pop();
+        Ok(OpVal::Null)
+    });
+    
+    // Start of the memory free for programs
+    pl.define_indirect("ldmemstartval", |p|{
+        Ok(OpVal::Int(p.model().mem_start))
+    });
+    
+    // Subscript a double word array, A + 8 * B
+    pl.define_indirect("wsubdb", |p|{
+        let a = p.stack.pop();
+        let b = p.stack.pop();
+        Ok(OpVal::Int(a.wrapping_add(b.wrapping_mul(8))))
+    });
+    
+    // Device identity
+    pl.define_indirect("lddevid", |p|{
+        Ok(OpVal::Int(p.model().device_id))
+    });
+    
+    // Give way to the next low priority process
+    pl.define_indirect("timeslice", |p|{
+        if p.priority() == Priority::Low && p.get_front_pointer(Priority::Low) != NOT_PROCESS_P{
+            p.deschedule()?;
+        }
+        Ok(OpVal::Null)
+    });
+}
+
+/// CRC, bit manipulation and 2D block moves, missing from the T414
+pub fn define_block_and_bit_ops(pl: &mut ProcLibrary){
+    // 2D block moves, C is the source stride, B the destination stride
+    // and A the number of rows
+    pl.define_indirect("move2dinit", |p|{
+        let a = p.stack.pop();
+        let b = p.stack.pop();
+        let c = p.stack.pop();
+        p.move2d = Move2d{
+            source_stride: c,
+            dest_stride: b,
+            length: a
+        };
+        Ok(OpVal::Null)
+    });
+    
+    // Copy A bytes wide rows from C to B
+    pl.define_indirect("move2dall", |p|{
+        move2d(p, |_| true)
+    });
+    
+    // Only copy the non zero bytes, for overlaying
+    pl.define_indirect("move2dnonzero", |p|{
+        move2d(p, |v| v != 0)
+    });
+    
+    // Only copy the zero bytes, for cutting out
+    pl.define_indirect("move2dzero", |p|{
+        move2d(p, |v| v == 0)
+    });
+    
+    // CRC of the word in C, generator in A and the CRC so far in B
+    pl.define_indirect("crcword", |p|{
+        let a = p.stack.pop();
+        let b = p.stack.pop();
+        let c = p.stack.pop();
+        Ok(OpVal::Int(crc_word(b as u32, c as u32, a as u32) as i32))
+    });
+    
+    // CRC of the byte in the top of C
+    pl.define_indirect("crcbyte", |p|{
+        let a = p.stack.pop();
+        let b = p.stack.pop();
+        let c = p.stack.pop();
+        Ok(OpVal::Int(crc_byte(b as u32, c as u32, a as u32) as i32))
+    });
+    
+    // Count of the bits set in A, added to B
+    pl.define_indirect("bitcnt", |p|{
+        let a = p.stack.pop();
+        let b = p.stack.pop();
+        Ok(OpVal::Int(b.wrapping_add(a.count_ones() as i32)))
+    });
+    
+    // Reverse the bits of A
+    pl.define_indirect("bitrevword", |p|{
+        let a = p.stack.pop();
+        Ok(OpVal::Int(a.reverse_bits()))
+    });
+    
+    // Reverse the bottom A bits of B, the rest of the result is zero
+    pl.define_indirect("bitrevnbits", |p|{
+        let a = p.stack.pop() as u32;
+        let b = p.stack.pop() as u32;
+        let reversed = b.reverse_bits();
+        let result = if a <= 32{
+            reversed.checked_shr(32 - a).unwrap_or(0)
+        }
+        else{
+            reversed.checked_shl(a - 32).unwrap_or(0)
+        };
+        Ok(OpVal::Int(result as i32))
+    });
+}
+
+/// Software floating point support for processors without an FPU.
+/// A single length value is handled as a fraction with the leading one,
+/// when there is one, in bit 31 and a biased exponent. The sign is left to
+/// the caller.
+pub fn define_soft_float(pl: &mut ProcLibrary){
+    // Unpack the single in A, A is the fraction, B the exponent and C is
+    // 4 * B + the type: 0 zero, 1 denormal, 2 normal, 3 infinity or NaN
+    pl.define_indirect("unpacksn", |p|{
+        let a = p.stack.pop();
+        let b = p.stack.pop();
+        let exp = (a >> 23) & 0xFF;
+        let mantissa = (a & 0x7F_FFFF) << 8;
+        let (frac, exp, kind) = match exp{
+            0 if mantissa == 0 => (0, 0, 0),
+            0 => (mantissa, 1, 1),
+            0xFF => (mantissa, 0xFF, 3),
+            _ => (mantissa | i32::MIN, exp, 2)
+        };
+        Ok(OpVal::List(vec![b.wrapping_mul(4).wrapping_add(kind), exp, frac]))
+    });
+    
+    // After norm, B:A is the normalised fraction and C the places shifted.
+    // The exponent is W[0] less C, denormal results are shifted back to
+    // exponent 0 keeping the lost bits in the bottom of the guard word.
+    pl.define_indirect("postnormsn", |p|{
+        let guard = p.stack.pop() as u32 as u64;
+        let frac = p.stack.pop() as u32 as u64;
+        let count = p.stack.pop();
+        let value = (frac << 32) | guard;
+        if value == 0{
+            return Ok(OpVal::List(vec![0, 0, 0]));
+        }
+        let exp = p.mem.read(p.workspace)?.wrapping_sub(count);
+        if exp > 0{
+            return Ok(OpVal::List(vec![exp, frac as i32, guard as i32]));
+        }
+        let shift = 1 - exp as i64;
+        let kept = if shift < 64 { value >> shift } else { 0 };
+        let sticky = (kept << shift.min(63) != value) as u64;
+        let value = kept | sticky;
+        Ok(OpVal::List(vec![0, (value >> 32) as i32, value as i32]))
+    });
+    
+    // Round the fraction in B with guard word A and exponent C to nearest,
+    // packing it into a single. Overflow gives infinity.
+    pl.define_indirect("roundsn", |p|{
+        let guard = p.stack.pop() as u32;
+        let frac = p.stack.pop() as u32;
+        let exp = p.stack.pop();
+        let mut significand = frac >> 8;
+        let round = frac & 0x80 != 0;
+        let sticky = frac & 0x7F != 0 || guard != 0;
+        if round && (sticky || significand & 1 != 0){
+            significand += 1;
+        }
+        // A carry out of the significand moves on to the exponent by itself
+        let packed = if exp <= 0{
+            significand as i64
+        }
+        else{
+            ((exp as i64 - 1) << 23) + significand as i64
+        };
+        Ok(OpVal::Int(packed.min(0x7F80_0000) as i32))
+    });
+    
+    // Load single length infinity
+    pl.define_indirect("ldinf", |_p|{
+        Ok(OpVal::Int(0x7F80_0000))
+    });
+    
+    // Fractional multiply, A and B are fractions with the binary point
+    // after bit 31 and the product is rounded to nearest
+    pl.define_indirect("fmul", |p|{
+        let a = p.stack.pop() as i64;
+        let b = p.stack.pop() as i64;
+        let result = (a * b + (1 << 30)) >> 31;
+        p.stack.push(result as i32);
+        if result != result as i32 as i64{
+            p.throw_error(OpErr::Overflow);
+        }
+        Ok(OpVal::Null)
+    });
+    
+    // Set the error flag if the single in A is infinity or NaN
+    pl.define_indirect("cflerr", |p|{
+        if p.stack.a() & 0x7F80_0000 == 0x7F80_0000{
+            p.throw_error(OpErr::Err);
+        }
+        Ok(OpVal::Null)
+    });
+}
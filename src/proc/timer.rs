@@ -0,0 +1,289 @@
+/* Processor clocks and timer queues */
+
+use std::thread;
+use std::time::{Duration, Instant};
+
+use super::{is_after, ATYPE, RTYPE, NOT_PROCESS_P, READY_P, STATE_S, TIME_S, TLINK_S, WAITING_P};
+use super::{DirectOp, OpErr, Priority, Proc};
+use crate::mem::TPTR_LOC;
+
+/// The low priority clock ticks once for every 64 high priority ticks
+const LOW_TICK: u64 = 64;
+
+/// Default microseconds a low priority process runs before giving way to another
+const TIMESLICE: u64 = 1024;
+
+/// Don't bother sleeping for less than this when pacing
+const MIN_SLEEP: Duration = Duration::from_millis(1);
+
+/// How emulated time relates to real time
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum TimerMode{
+    /// Time only advances with executed cycles, runs are repeatable
+    Emulated,
+    /// Same as Emulated, but execution is slowed down to keep up with the wall clock
+    Paced
+}
+
+/// Clock registers and timer queues for both priorities
+pub struct Timers{
+    mode: TimerMode,
+    /// Processor cycles in one microsecond, the clock speed in MHz
+    cycles_per_microsecond: u64,
+    /// Cycles since reset
+    cycles: u64,
+    /// Clock values at cycle 0, indexed by priority
+    base: [RTYPE; 2],
+    /// Wall clock time of cycle 0 when pacing
+    started: Option<Instant>,
+    /// Timeslice period in cycles
+    timeslice: u64,
+    /// Cycle the current low priority process started its timeslice
+    slice_start: u64,
+    /// Word holding the instructions being executed, fetching another costs a memory access
+    fetched: Option<ATYPE>
+}
+
+impl Timers{
+    pub fn new(clock_mhz: u64) -> Self{
+        Self{
+            mode: TimerMode::Emulated,
+            cycles_per_microsecond: clock_mhz,
+            cycles: 0,
+            base: [0; 2],
+            started: None,
+            timeslice: TIMESLICE * clock_mhz,
+            slice_start: 0,
+            fetched: None
+        }
+    }
+
+    fn microseconds(&self) -> u64{
+        self.cycles / self.cycles_per_microsecond
+    }
+
+    /// Clock ticks since reset
+    fn ticks(&self, pri: Priority) -> u64{
+        match pri{
+            Priority::High => self.microseconds(),
+            Priority::Low => self.microseconds() / LOW_TICK
+        }
+    }
+}
+
+impl Proc{
+    /// Choose between repeatable emulated time and wall clock pacing
+    pub fn set_timer_mode(&mut self, mode: TimerMode){
+        self.timers.mode = mode;
+        self.timers.started = None;
+    }
+
+    /// Processor cycles executed, including time spent idle
+    pub fn cycles(&self) -> u64{
+        self.timers.cycles
+    }
+
+    /// Processor cycles in one microsecond
+    pub fn cycles_per_microsecond(&self) -> u64{
+        self.timers.cycles_per_microsecond
+    }
+
+    /// How long the cycles executed so far take on real hardware
+    pub fn emulated_time(&self) -> Duration{
+        Duration::from_nanos(self.timers.cycles * 1000 / self.timers.cycles_per_microsecond)
+    }
+
+    /// Datasheet cycles of an instruction byte, code is the full operand
+    /// including prefixes. Run before executing it, as some operations
+    /// take longer depending on the registers.
+    pub(super) fn instruction_cycles(&self, instruction: u8, code: RTYPE) -> u64{
+        let op = DirectOp::from_instruction(instruction);
+        if op != DirectOp::OPR{
+            return op.info().cycles;
+        }
+        let entry = match self.library.get_indirect_info(code as usize){
+            Some(entry) => entry,
+            None => return op.info().cycles
+        };
+        let a = self.stack.a();
+        let b = self.stack.b();
+        let extra = match entry.mnemonic{
+            // One cycle for each place shifted
+            "shl" | "shr" | "lshl" | "lshr" => a as u32 as u64,
+            // Two cycles for each word copied
+            "move" => 2 * (a.max(0) as u64).div_ceil(4),
+            // One cycle for each bit of the multiplier
+            "prod" => (32 - a.leading_zeros()) as u64,
+            "norm" if a == 0 && b == 0 => return 3,
+            "norm" => (((b as u32 as u64) << 32) | a as u32 as u64).leading_zeros() as u64,
+            "fpentry" => self.library.get_fpentry_info(a as usize).map_or(0, |e| e.cycles),
+            _ => 0
+        };
+        entry.cycles + extra
+    }
+
+    /// Wait states of fetching the word holding the instruction at iptr, if
+    /// it isn't the word already being executed
+    pub(super) fn fetch_cycles(&mut self, iptr: ATYPE) -> u64{
+        let word = iptr & !0b11;
+        if self.timers.fetched == Some(word){
+            return 0;
+        }
+        self.timers.fetched = Some(word);
+        if self.mem.is_on_chip(word) { 0 } else { self.model.external_wait_states }
+    }
+
+    /// Extra cycles for a number of external memory accesses
+    pub(super) fn wait_states(&self, accesses: u64) -> u64{
+        accesses * self.model.external_wait_states
+    }
+
+    /// Set how long, in microseconds, a low priority process runs before it is timesliced
+    pub fn set_timeslice(&mut self, microseconds: u64){
+        self.timers.timeslice = microseconds * self.timers.cycles_per_microsecond;
+    }
+
+    /// A new process starts a full timeslice
+    pub(super) fn start_timeslice(&mut self){
+        self.timers.slice_start = self.timers.cycles;
+    }
+
+    pub(super) fn timeslice_expired(&self) -> bool{
+        self.timers.cycles - self.timers.slice_start >= self.timers.timeslice
+    }
+
+    /// Get the value of the clock register
+    pub(super) fn get_clock_register(&self, pri: Priority) -> RTYPE{
+        self.timers.base[pri as usize].wrapping_add(self.timers.ticks(pri) as RTYPE)
+    }
+
+    /// Set both clock registers to the same value
+    pub(super) fn set_clock_registers(&mut self, v: RTYPE){
+        for pri in [Priority::High, Priority::Low]{
+            self.timers.base[pri as usize] = v.wrapping_sub(self.timers.ticks(pri) as RTYPE);
+        }
+    }
+
+    /// Front of a timer queue, kept in TPtrLoc0/1
+    fn timer_front(&self, pri: Priority) -> Result<ATYPE, OpErr>{
+        Ok(self.mem.read(TPTR_LOC.wrapping_add(4 * pri as RTYPE))?)
+    }
+
+    fn set_timer_front(&mut self, pri: Priority, waddress: ATYPE) -> Result<(), OpErr>{
+        Ok(self.mem.write(TPTR_LOC.wrapping_add(4 * pri as RTYPE), waddress)?)
+    }
+
+    /// Any process waiting on a timer
+    pub fn timers_pending(&self) -> bool{
+        [Priority::High, Priority::Low].into_iter().any(|pri| matches!(self.timer_front(pri), Ok(t) if t != NOT_PROCESS_P))
+    }
+
+    /// Deschedule the current process until its clock reaches time
+    pub(super) fn timer_wait(&mut self, time: RTYPE) -> Result<(), OpErr>{
+        self.mem.write(self.workspace.wrapping_add(TIME_S), time)?;
+        self.mem.write(self.workspace.wrapping_add(STATE_S), WAITING_P)?;
+        self.insert_timer(self.priority(), self.workspace, time)?;
+        self.wait()
+    }
+
+    /// Insert a process into a timer queue, sorted by Time.s and linked through TLink.s
+    pub(super) fn insert_timer(&mut self, pri: Priority, waddress: ATYPE, time: RTYPE) -> Result<(), OpErr>{
+        let mut previous = NOT_PROCESS_P;
+        let mut next = self.timer_front(pri)?;
+        while next != NOT_PROCESS_P && !is_after(self.mem.read(next.wrapping_add(TIME_S))?, time){
+            previous = next;
+            next = self.mem.read(next.wrapping_add(TLINK_S))?;
+        }
+        self.mem.write(waddress.wrapping_add(TLINK_S), next)?;
+        if previous == NOT_PROCESS_P{
+            self.set_timer_front(pri, waddress)?;
+        }
+        else{
+            self.mem.write(previous.wrapping_add(TLINK_S), waddress)?;
+        }
+        Ok(())
+    }
+
+    /// Take a process out of a timer queue if it is in it
+    pub(super) fn remove_timer(&mut self, pri: Priority, waddress: ATYPE) -> Result<(), OpErr>{
+        let mut previous = NOT_PROCESS_P;
+        let mut next = self.timer_front(pri)?;
+        while next != NOT_PROCESS_P{
+            let link = self.mem.read(next.wrapping_add(TLINK_S))?;
+            if next == waddress{
+                if previous == NOT_PROCESS_P{
+                    self.set_timer_front(pri, link)?;
+                }
+                else{
+                    self.mem.write(previous.wrapping_add(TLINK_S), link)?;
+                }
+                break;
+            }
+            previous = next;
+            next = link;
+        }
+        Ok(())
+    }
+
+    /// Move time forward and wake any process whose time has arrived
+    pub(super) fn advance_time(&mut self, cycles: u64) -> Result<(), OpErr>{
+        self.timers.cycles += cycles;
+        if self.timers.mode == TimerMode::Paced{
+            let started = *self.timers.started.get_or_insert_with(Instant::now);
+            let emulated = self.emulated_time();
+            let wall = started.elapsed();
+            if emulated > wall + MIN_SLEEP{
+                thread::sleep(emulated - wall);
+            }
+        }
+        self.wake_timers()
+    }
+
+    fn wake_timers(&mut self) -> Result<(), OpErr>{
+        for pri in [Priority::High, Priority::Low]{
+            loop{
+                let front = self.timer_front(pri)?;
+                if front == NOT_PROCESS_P{
+                    break;
+                }
+                let time = self.mem.read(front.wrapping_add(TIME_S))?;
+                if is_after(time, self.get_clock_register(pri)){
+                    break;
+                }
+                let next = self.mem.read(front.wrapping_add(TLINK_S))?;
+                self.set_timer_front(pri, next)?;
+
+                // An ALT may already have been made ready by a channel
+                let state = front.wrapping_add(STATE_S);
+                if self.mem.read(state)? == WAITING_P{
+                    self.mem.write(state, READY_P)?;
+                    self.run_process(front | pri as RTYPE)?;
+                }
+            }
+        }
+        Ok(())
+    }
+
+    /// While idle, skip forward to the first timer that expires
+    pub(super) fn idle_until_timer(&mut self) -> Result<(), OpErr>{
+        let now = self.timers.microseconds();
+        let mut wake = None;
+        for pri in [Priority::High, Priority::Low]{
+            let front = self.timer_front(pri)?;
+            if front == NOT_PROCESS_P{
+                continue;
+            }
+            let ticks = self.mem.read(front.wrapping_add(TIME_S))?
+                .wrapping_sub(self.get_clock_register(pri)).max(0) as u64;
+            let at = match pri{
+                Priority::High => now + ticks,
+                Priority::Low => (self.timers.ticks(pri) + ticks) * LOW_TICK
+            };
+            wake = Some(wake.map_or(at, |w: u64| w.min(at)));
+        }
+        match wake{
+            Some(at) => self.advance_time((at * self.timers.cycles_per_microsecond).saturating_sub(self.timers.cycles)),
+            None => Ok(())
+        }
+    }
+}
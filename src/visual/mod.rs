@@ -1,6 +1,6 @@
-use std::{io, thread, time::Duration};
+use std::io;
 use tui::{
-    backend::{Backend, CrosstermBackend}, layout::{Constraint, Direction, Layout, Rect}, style::Modifier, widgets::{Block, Borders, Paragraph, Widget}, Frame, Terminal
+    backend::{Backend, CrosstermBackend}, layout::{Constraint, Direction, Layout}, style::Modifier, widgets::{Block, Borders, Paragraph}, Frame, Terminal
 };
 use crossterm::{
     event::{self, DisableMouseCapture, EnableMouseCapture, Event, KeyCode, KeyEventKind},
@@ -10,8 +10,7 @@ use crossterm::{
 use tui::{style::{Color, Style}, widgets::{Row, Table}};
 
 /* Making a cute visualizer for processor state */
-use crate::{mem::Mem, proc::{DirectOp, Proc}};
-mod strings;
+use crate::{mem::{Mem, MemErr}, proc::{DirectOp, ExecError, LoadConfig, Proc}};
 
 
 
@@ -38,12 +37,12 @@ impl StyleTag{
                 .add_modifier(Modifier::SLOW_BLINK)
                 .add_modifier(Modifier::BOLD);
         }
-        return Style::default().fg(self.color);
+        Style::default().fg(self.color)
     }
 }
 
 trait RowEntry{
-    fn as_row(&self, number: usize, active: bool) -> Row;
+    fn as_row(&self, number: usize, active: bool) -> Row<'_>;
 }
 
 struct Instruction{
@@ -59,7 +58,7 @@ impl Instruction{
     fn new(op: DirectOp, value: i32) -> Self{
         Self{
             operation: op,
-            value: value,
+            value,
             breakpoint: false,
             style: StyleTag::default(),
             alias: None
@@ -68,7 +67,7 @@ impl Instruction{
 }
 
 impl RowEntry for Instruction{
-    fn as_row(&self, number: usize, active: bool) -> Row{
+    fn as_row(&self, number: usize, active: bool) -> Row<'_>{
         let mut v = Vec::new();
         
         v.push(format!("{:#02X}", number));
@@ -80,7 +79,7 @@ impl RowEntry for Instruction{
             v.push(" ".to_string());
         }
         
-        v.push(format!("{:?}", self.operation).to_lowercase());
+        v.push(self.operation.info().mnemonic.to_string());
         v.push(format!("{:#01X}", self.value));
         
         // Additional column
@@ -97,14 +96,6 @@ impl Instruction{
     fn set_alias(&mut self, s: String){
         self.alias = Some(s);
     }
-    
-    fn toggle_breakpont(&mut self){
-        self.breakpoint = !self.breakpoint;
-    }
-    
-    fn as_u8(&self) -> u8{
-        ((self.operation.clone() as u8) << 4) + (self.value as u8)
-    }
 }
 
 struct ScrollTable<T:RowEntry>{
@@ -128,7 +119,7 @@ impl<T:RowEntry> Default for ScrollTable<T>{
 }
 
 impl<T:RowEntry> ScrollTable<T>{
-    fn as_rows(&self) -> Vec<Row>{
+    fn as_rows(&self) -> Vec<Row<'_>>{
         let mut v = Vec::new();
         let max_value = self.display_pointer + self.height;
         for i in self.display_pointer..max_value{
@@ -144,7 +135,7 @@ impl<T:RowEntry> ScrollTable<T>{
         }
         v
     }
-    pub fn as_table(&self) -> Table{
+    pub fn as_table(&self) -> Table<'_>{
         Table::new(self.as_rows())
     }
     pub fn up(&mut self){
@@ -173,14 +164,14 @@ struct IntEntry{
 impl IntEntry{
     fn new(label: String) -> Self{
         Self{
-            label: label,
+            label,
             value: 0
         }
     }
 }
 
 impl RowEntry for IntEntry{
-    fn as_row(&self, _number: usize, _active: bool) -> Row {
+    fn as_row(&self, _number: usize, _active: bool) -> Row<'_> {
         Row::new(vec![
             self.label.clone(),
             format!("{:#08X}", self.value)
@@ -196,14 +187,14 @@ struct MemoryEntry{
 impl MemoryEntry{
     fn new(address: i32, value: i32) -> Self{
         Self{
-            address: address,
-            value: value
+            address,
+            value
         }
     }
 }
 
 impl RowEntry for MemoryEntry{
-    fn as_row(&self, _number: usize, active: bool) -> Row {
+    fn as_row(&self, _number: usize, active: bool) -> Row<'_> {
         if active{
             return Row::new(vec![
                 format!("{:#08X}", self.address),
@@ -241,11 +232,10 @@ impl ProcessMemory{
         for i in 0..self.table.height{
             
             let offset = i as i32 - (self.table.height >> 1) as i32;
-            let address = workspace + (offset << 2);
+            let address = workspace.wrapping_add(offset << 2);
             
-            if address >= 0{
-                let entry = m.read(address);
-                let me = MemoryEntry::new(address as i32, entry);
+            if let Ok(entry) = m.read(address){
+                let me = MemoryEntry::new(address, entry);
             
                 self.table.contents.push(me);
                 if address == workspace{
@@ -265,14 +255,16 @@ struct Stack{
 
 impl Stack{
     fn new() -> Self{
-        let mut table = ScrollTable::default();
-        table.contents = vec![
-            IntEntry::new("A".to_string()),
-            IntEntry::new("B".to_string()),
-            IntEntry::new("C".to_string())
-        ];
+        let table = ScrollTable{
+            contents: vec![
+                IntEntry::new("A".to_string()),
+                IntEntry::new("B".to_string()),
+                IntEntry::new("C".to_string())
+            ],
+            ..ScrollTable::default()
+        };
         Self{
-            table: table
+            table
         }
     }
 
@@ -286,25 +278,57 @@ impl Stack{
 pub struct ProcessorTui{
     proc: Proc,
     mem: Mem,
+    image: Vec<u8>,
+    config: LoadConfig,
     instructions: ScrollTable<Instruction>,
     register: Stack,
-    memdisplay: ProcessMemory
+    memdisplay: ProcessMemory,
+    // Fault that stopped the last step or run
+    error: Option<ExecError>
 }
 
 impl ProcessorTui{
     pub fn new(p: Proc) -> Self{
         let m = p.mem_reference();
+        let config = LoadConfig::new(p.program_counter(), p.workspace_pointer());
         Self{
             mem: m.clone(),
             proc: p,
+            image: Vec::new(),
+            config,
             instructions: ScrollTable::default(),
             register: Stack::new(),
-            memdisplay: ProcessMemory::new()
+            memdisplay: ProcessMemory::new(),
+            error: None
         }
     }
     
+    /// Load a program image into the processor and instruction view
+    pub fn load(&mut self, image: Vec<u8>, config: LoadConfig) -> Result<(), MemErr>{
+        self.instructions.contents = image.iter()
+            .map(|b| Instruction::new(DirectOp::from_instruction(*b), (b & 0xF) as i32))
+            .collect();
+        self.image = image;
+        self.config = config;
+        self.proc.load_image(&self.image, &self.config)?;
+        Ok(())
+    }
+    
     pub fn upload_instruction(&mut self, op: DirectOp, value: i32){
-        self.instructions.contents.push(Instruction::new(op, value));
+        let mut image = self.image.clone();
+        image.push(((op as u8) << 4) + (value as u8));
+        let _ = self.load(image, self.config.clone());
+    }
+    
+    /// Index of the instruction at Iptr, if it is inside the program
+    fn active_instruction(&self) -> Option<usize>{
+        let offset = self.proc.program_counter() - self.config.address;
+        if offset >= 0 && (offset as usize) < self.instructions.contents.len(){
+            Some(offset as usize)
+        }
+        else{
+            None
+        }
     }
     
     fn draw<B: Backend>(&self, f: &mut Frame<B>){
@@ -343,7 +367,11 @@ impl ProcessorTui{
         
         f.render_widget(commands, v_layout[0]);
         
-        let p = Paragraph::new(format!("Program Counter: {:#04X}", self.proc.program_counter()));
+        let p = match &self.error{
+            Some(e) => Paragraph::new(format!("Program Counter: {:#04X}  Fault: {}", self.proc.program_counter(), e))
+                .style(Style::default().fg(Color::Red)),
+            None => Paragraph::new(format!("Program Counter: {:#04X}", self.proc.program_counter()))
+        };
             
         f.render_widget(p, v_layout[1]);
             
@@ -378,42 +406,45 @@ impl ProcessorTui{
     
     fn update_alias(&mut self){
         let c = &mut self.instructions.contents;
+        let mut operand: i32 = 0;
         for i in 0..c.len(){
-            if c[i].operation == DirectOp::OPR{
-                if i > 0{
-                    let mut prefix = 0;
-                    if c[i-1].operation == DirectOp::PFIX{
-                        prefix = c[i-1].value;
-                        c[i - 1].set_alias("┑".to_string());
-                    }
-                    let value = c[i].value;
-                    // TODO get actual name
-                    c[i].set_alias(format!("┛ 0x2{:01X} 0xF{:01X}", prefix, value));
-                }
+            let value = operand.wrapping_add(c[i].value);
+            match c[i].operation{
+                DirectOp::PFIX | DirectOp::NFIX => {
+                    operand = if c[i].operation == DirectOp::PFIX { value << 4 } else { (!value) << 4 };
+                    c[i].set_alias("┑".to_string());
+                },
+                DirectOp::OPR => {
+                    operand = 0;
+                    let name = match self.proc.indirect_info(value as usize){
+                        Some(info) => info.mnemonic.to_string(),
+                        None => format!("opr {:#x}", value)
+                    };
+                    c[i].set_alias(format!("┛ {}", name));
+                },
+                _ if i > 0 && matches!(c[i - 1].operation, DirectOp::PFIX | DirectOp::NFIX) => {
+                    operand = 0;
+                    c[i].set_alias(format!("┛ {:#x}", value));
+                },
+                _ => operand = 0
             }
         }
     }
     
     fn update(&mut self){
-        let inst = &mut self.instructions.contents;
-        
         // update program counter highlight
-        let pc_p = self.proc.program_counter() as usize;
+        let active = self.active_instruction();
+        let inst = &mut self.instructions.contents;
         
-        for i in 0..inst.len(){
-            if i == pc_p{
-                inst[i].style.color = Color::Green;
+        for (i, entry) in inst.iter_mut().enumerate(){
+            if Some(i) == active{
+                entry.style.color = Color::Green;
             }
             else{
-                inst[i].style.color = COLOR_STANDARD;
+                entry.style.color = COLOR_STANDARD;
             }
         }
-        if pc_p >= self.instructions.contents.len(){
-            self.instructions.footer = true;
-        }
-        else{
-            self.instructions.footer = false;
-        }
+        self.instructions.footer = active.is_none();
         
         // update register values
         self.register.update(&self.proc);
@@ -422,51 +453,45 @@ impl ProcessorTui{
     
     /// Clear process registers and program counter
     fn clear(&mut self){
-        //self.proc.reset(0x8000);
-        todo!("Clear not implemented");
+        // Reloading the image resets Iptr and Wptr
+        let _ = self.proc.load_image(&self.image, &self.config);
+        self.error = None;
         self.update();
     }
     
     fn step(&mut self){
-        let pc = self.proc.program_counter();
-        
-        let inst = &mut self.instructions.contents;
-        
-        if pc < inst.len() as i32{
-            let is = &inst[pc as usize];
-            
-            self.proc.run(is.as_u8());
+        if self.active_instruction().is_some(){
+            self.run_checked();
         }
         
         self.update();
     }
     
-    fn run_checked(&mut self, pc: usize) -> bool{
-        let is = &self.instructions.contents[pc];
-        if let Err(_e) = self.proc.run(is.as_u8()){
-            // TODO print error
-            return false;
+    /// Step, keeping any fault to show in the status line
+    fn run_checked(&mut self) -> bool{
+        match self.proc.step(){
+            Ok(()) => true,
+            Err(e) => {
+                self.error = Some(e);
+                false
+            }
         }
-        return true;
     }
     
     /// Run program from cleared state 
     fn run_program(&mut self){
         self.clear();
-        let inst = &mut self.instructions.contents;
-        
-        let len = inst.len();
         
         for _ in 0..500{ // dont run infinitely is all
-            let pc = self.proc.program_counter();
-            if pc < len as i32{
-                if self.instructions.contents[pc as usize].breakpoint{
+            if let Some(i) = self.active_instruction(){
+                if self.instructions.contents[i].breakpoint{
                     self.update();
                     return;
                 }
                 
-                let result = self.run_checked(pc as usize);
+                let result = self.run_checked();
                 if !result{
+                    self.update();
                     return;
                 }
                 
@@ -490,23 +515,20 @@ impl ProcessorTui{
             terminal.draw(|f| self.draw(f))?;
             
             if let Event::Key(key) = event::read()? {
-                match key.kind{
-                    KeyEventKind::Release => {
-                        match key.code{
-                            KeyCode::Up => self.instructions.up(),
-                            KeyCode::Down => self.instructions.down(),
-                            KeyCode::Char('b') => self.instructions.active().breakpoint = true,
-                            KeyCode::Char('B') => self.instructions.active().breakpoint = false,
-                            KeyCode::Char('s') => self.step(),
-                            KeyCode::Char('c') => self.clear(),
-                            KeyCode::Char('r') => self.run_program(),
-                            KeyCode::Char('q') => {
-                                return Ok(())
-                            }
-                            _ => ()
+                if key.kind == KeyEventKind::Release{
+                    match key.code{
+                        KeyCode::Up => self.instructions.up(),
+                        KeyCode::Down => self.instructions.down(),
+                        KeyCode::Char('b') => self.instructions.active().breakpoint = true,
+                        KeyCode::Char('B') => self.instructions.active().breakpoint = false,
+                        KeyCode::Char('s') => self.step(),
+                        KeyCode::Char('c') => self.clear(),
+                        KeyCode::Char('r') => self.run_program(),
+                        KeyCode::Char('q') => {
+                            return Ok(())
                         }
-                    },
-                    _ => ()
+                        _ => ()
+                    }
                 }
                 
             }
@@ -533,10 +555,6 @@ impl ProcessorTui{
         )?;
         terminal.show_cursor()?;
         
-        if let Err(err) = res {
-            println!("{:?}", err)
-        }
-    
-        Ok(())
+        res
     }
 }
\ No newline at end of file